@@ -0,0 +1,124 @@
+//! How the alpha channel takes part in the comparison.
+
+use image::{ColorType, DynamicImage, GenericImageView, Rgb, Rgb32FImage, Rgba};
+
+use crate::{normalize_color, Mask};
+
+/// The background `AlphaMode::Composite` uses when none is given
+pub const DEFAULT_ALPHA_BACKGROUND: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+/// How to compare images with an alpha channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AlphaMode {
+    /// Compare alpha like any other channel, and color even where it is hidden
+    #[default]
+    Raw,
+    /// Compare how the images look drawn over a background of the given color;
+    /// the alpha of the color is not used
+    Composite(Rgba<u8>),
+    /// Pixels that are fully transparent in both images are equal, whatever their
+    /// color
+    TransparentEqual,
+    /// Leave the alpha channel out and compare the color as it is stored
+    Ignore,
+}
+
+impl AlphaMode {
+    /// Bring two images of the same dimensions and color type into the form this
+    /// mode compares. Returns `None` when there is nothing to do, because the mode
+    /// is raw or the images have no alpha channel.
+    pub fn apply(
+        self,
+        image1: &DynamicImage,
+        image2: &DynamicImage,
+    ) -> Option<(DynamicImage, DynamicImage)> {
+        if !image1.color().has_alpha() {
+            return None;
+        }
+        match self {
+            AlphaMode::Raw => None,
+            AlphaMode::Composite(background) => {
+                Some((composite(image1, background), composite(image2, background)))
+            }
+            AlphaMode::TransparentEqual => {
+                let (width, height) = image1.dimensions();
+                let transparent = |image: &DynamicImage| -> Vec<bool> {
+                    image.to_rgba32f().pixels().map(|p| p[3] <= 0.0).collect()
+                };
+                let both = transparent(image1)
+                    .iter()
+                    .zip(transparent(image2))
+                    .map(|(&t1, t2)| t1 && t2)
+                    .collect();
+                let mask = Mask::from_flags(width, height, both);
+                Some((image1.clone(), mask.neutralize(image1, image2)))
+            }
+            AlphaMode::Ignore => {
+                let color = without_alpha(image1.color());
+                Some((
+                    normalize_color(image1, color),
+                    normalize_color(image2, color),
+                ))
+            }
+        }
+    }
+}
+
+/// The color type with the same channels and depth, minus alpha
+fn without_alpha(color: ColorType) -> ColorType {
+    match color {
+        ColorType::La8 => ColorType::L8,
+        ColorType::Rgba8 => ColorType::Rgb8,
+        ColorType::La16 => ColorType::L16,
+        ColorType::Rgba16 => ColorType::Rgb16,
+        ColorType::Rgba32F => ColorType::Rgb32F,
+        other => other,
+    }
+}
+
+/// Draw an image over a background of the given color, keeping its channels and
+/// depth but dropping alpha
+fn composite(image: &DynamicImage, background: Rgba<u8>) -> DynamicImage {
+    let rgba = image.to_rgba32f();
+    let background = background.0.map(|c| f32::from(c) / 255.0);
+    let flat = Rgb32FImage::from_fn(image.width(), image.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let over = |c: f32, bg: f32| c * a + bg * (1.0 - a);
+        Rgb([
+            over(r, background[0]),
+            over(g, background[1]),
+            over(b, background[2]),
+        ])
+    });
+    normalize_color(
+        &DynamicImage::ImageRgb32F(flat),
+        without_alpha(image.color()),
+    )
+}
+
+#[test]
+fn test_alpha_modes() {
+    // An opaque gray pixel, a half transparent black one and two fully
+    // transparent ones with different hidden colors
+    let image = |hidden: u8| {
+        DynamicImage::ImageLumaA8(image::GrayAlphaImage::from_fn(4, 1, |x, _| match x {
+            0 => image::LumaA([100, 255]),
+            1 => image::LumaA([0, 128]),
+            _ => image::LumaA([hidden, 0]),
+        }))
+    };
+    let (image1, image2) = (image(0), image(200));
+
+    assert_eq!(AlphaMode::Raw.apply(&image1, &image2), None);
+    let (_, equal) = AlphaMode::TransparentEqual.apply(&image1, &image2).unwrap();
+    assert_eq!(equal, image1);
+
+    let (flat, _) = AlphaMode::Composite(DEFAULT_ALPHA_BACKGROUND)
+        .apply(&image1, &image2)
+        .unwrap();
+    assert_eq!(flat.color(), ColorType::L8);
+    assert_eq!(flat.to_luma8().into_raw(), vec![100, 127, 255, 255]);
+
+    let (stripped, _) = AlphaMode::Ignore.apply(&image1, &image2).unwrap();
+    assert_eq!(stripped.to_luma8().into_raw(), vec![100, 0, 0, 0]);
+}
@@ -0,0 +1,137 @@
+//! Outputs for spotting subtle shifts by eye: an animation flipping between the
+//! images, and an onion skin blending them.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::error::{EncodingError, ImageFormatHint, UnsupportedError, UnsupportedErrorKind};
+use image::{imageops, Delay, DynamicImage, Frame, ImageError, ImageFormat, Rgba, RgbaImage};
+
+use crate::DiffError;
+
+/// How long each frame of a blink animation is shown unless another delay is given
+pub const DEFAULT_FRAME_DELAY_MS: u16 = 500;
+
+/// How much of the second image shows in an onion skin unless another opacity is given
+pub const DEFAULT_ONION_OPACITY: f64 = 0.5;
+
+/// Speed of the GIF color quantizer, from 1 (best quality) to 30 (fastest)
+const GIF_SPEED: i32 = 10;
+
+/// The frames of a blink animation: the first image, the second image and, if
+/// given, the diff. Frames of different sizes are padded with transparent pixels
+/// to the largest one.
+pub fn blink_frames(
+    image1: &DynamicImage,
+    image2: &DynamicImage,
+    diff: Option<&DynamicImage>,
+) -> Vec<RgbaImage> {
+    let mut images = vec![image1, image2];
+    images.extend(diff);
+    let width = images.iter().map(|i| i.width()).max().unwrap_or(0);
+    let height = images.iter().map(|i| i.height()).max().unwrap_or(0);
+    images
+        .iter()
+        .map(|image| {
+            let mut frame = RgbaImage::new(width, height);
+            imageops::replace(&mut frame, &image.to_rgba8(), 0, 0);
+            frame
+        })
+        .collect()
+}
+
+/// Write frames as an endlessly looping animation, as a GIF or an animated PNG
+/// depending on the file extension. All frames must have the same size.
+pub fn save_animation(
+    frames: &[RgbaImage],
+    delay_ms: u16,
+    filename: &str,
+) -> Result<(), DiffError> {
+    let save_error = |source| DiffError::Save {
+        path: filename.to_string(),
+        source,
+    };
+    let format = ImageFormat::from_path(filename).map_err(save_error)?;
+    // Only created once the format is known to be supported, so that an
+    // unsupported extension does not leave an empty file behind
+    let create = || {
+        File::create(Path::new(filename))
+            .map(BufWriter::new)
+            .map_err(|e| save_error(ImageError::IoError(e)))
+    };
+
+    match format {
+        ImageFormat::Gif => {
+            let mut encoder = GifEncoder::new_with_speed(create()?, GIF_SPEED);
+            encoder.set_repeat(Repeat::Infinite).map_err(save_error)?;
+            let delay = Delay::from_numer_denom_ms(u32::from(delay_ms), 1);
+            let frames = frames
+                .iter()
+                .map(|f| Frame::from_parts(f.clone(), 0, 0, delay));
+            encoder.encode_frames(frames).map_err(save_error)
+        }
+        ImageFormat::Png => write_apng(create()?, frames, delay_ms).map_err(|e| {
+            save_error(ImageError::Encoding(EncodingError::new(
+                ImageFormatHint::Exact(ImageFormat::Png),
+                e,
+            )))
+        }),
+        _ => Err(save_error(ImageError::Unsupported(
+            UnsupportedError::from_format_and_kind(
+                ImageFormatHint::Exact(format),
+                UnsupportedErrorKind::GenericFeature("animation".to_string()),
+            ),
+        ))),
+    }
+}
+
+fn write_apng(
+    writer: BufWriter<File>,
+    frames: &[RgbaImage],
+    delay_ms: u16,
+) -> Result<(), png::EncodingError> {
+    let (width, height) = frames.first().map(|f| f.dimensions()).unwrap_or((0, 0));
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    // Zero plays means the animation loops forever
+    encoder.set_animated(frames.len() as u32, 0)?;
+    encoder.set_frame_delay(delay_ms, 1000)?;
+    let mut writer = encoder.write_header()?;
+    for frame in frames {
+        writer.write_image_data(frame.as_raw())?;
+    }
+    writer.finish()
+}
+
+/// Blend the second image over the first with the given opacity, from 0 (only the
+/// first image) to 1 (only the second). The result has the size of the first image.
+pub fn onion_skin(image1: &DynamicImage, image2: &DynamicImage, opacity: f64) -> RgbaImage {
+    let opacity = opacity.clamp(0.0, 1.0);
+    let base = image1.to_rgba8();
+    let top = image2.to_rgba8();
+    RgbaImage::from_fn(base.width(), base.height(), |x, y| {
+        let p1 = base.get_pixel(x, y).0;
+        let p2 = if x < top.width() && y < top.height() {
+            top.get_pixel(x, y).0
+        } else {
+            p1
+        };
+        let mix = |c: usize| {
+            (f64::from(p1[c]) * (1.0 - opacity) + f64::from(p2[c]) * opacity).round() as u8
+        };
+        Rgba([mix(0), mix(1), mix(2), mix(3)])
+    })
+}
+
+#[test]
+fn test_onion_skin() {
+    let black = DynamicImage::ImageRgb8(image::RgbImage::new(2, 1));
+    let white = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(1, 1, image::Rgb([255; 3])));
+    let blended = onion_skin(&black, &white, 0.25);
+    assert_eq!(blended.get_pixel(0, 0), &Rgba([64, 64, 64, 255]));
+    // Where the second image does not reach, the first shows unchanged
+    assert_eq!(blended.get_pixel(1, 0), &Rgba([0, 0, 0, 255]));
+}
@@ -0,0 +1,139 @@
+//! Recognising anti-aliased edge pixels, so that differences in how text and
+//! shapes were smoothed can be told apart from real changes. The detection
+//! follows pixelmatch.
+
+use image::{DynamicImage, GenericImageView, Rgba};
+
+use crate::{changed_pixels, Mask};
+
+/// Color of anti-aliased pixels on the diff image unless another is given
+pub const DEFAULT_ANTIALIASED_COLOR: Rgba<u8> = Rgba([255, 255, 0, 255]);
+
+/// The pixels of an image as exact RGBA values and as brightness
+struct Pixels {
+    width: u32,
+    height: u32,
+    rgba: Vec<[f32; 4]>,
+    brightness: Vec<f32>,
+}
+
+impl Pixels {
+    fn of(image: &DynamicImage) -> Pixels {
+        let (width, height) = image.dimensions();
+        let rgba: Vec<[f32; 4]> = image.to_rgba32f().pixels().map(|p| p.0).collect();
+        let brightness = rgba
+            .iter()
+            .map(|&[r, g, b, a]| {
+                // Transparent pixels are seen against white
+                let blend = |c: f32| 1.0 + (c - 1.0) * a;
+                0.298_895_3 * blend(r) + 0.586_622_5 * blend(g) + 0.114_482_2 * blend(b)
+            })
+            .collect();
+        Pixels {
+            width,
+            height,
+            rgba,
+            brightness,
+        }
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        y as usize * self.width as usize + x as usize
+    }
+
+    fn on_edge(&self, x: u32, y: u32) -> bool {
+        x == 0 || y == 0 || x + 1 == self.width || y + 1 == self.height
+    }
+
+    /// The pixels around (x, y) that lie within the image
+    fn neighbours(&self, x: u32, y: u32) -> impl Iterator<Item = (u32, u32)> {
+        let (width, height) = (self.width, self.height);
+        (y.saturating_sub(1)..(y + 2).min(height))
+            .flat_map(move |ny| (x.saturating_sub(1)..(x + 2).min(width)).map(move |nx| (nx, ny)))
+            .filter(move |&n| n != (x, y))
+    }
+
+    /// Whether more than two pixels around (x, y) have exactly its color, where
+    /// the image edge counts as one
+    fn has_many_siblings(&self, x: u32, y: u32) -> bool {
+        let color = self.rgba[self.index(x, y)];
+        let same = self
+            .neighbours(x, y)
+            .filter(|&(nx, ny)| self.rgba[self.index(nx, ny)] == color)
+            .count();
+        same + usize::from(self.on_edge(x, y)) > 2
+    }
+
+    /// Whether the pixel at (x, y) looks anti-aliased: it has both darker and
+    /// brighter neighbours, at most two of the same brightness (counting the image
+    /// edge as one), and its darkest or brightest neighbour lies in an area of flat
+    /// color in both this and the other image
+    fn is_antialiased(&self, other: &Pixels, x: u32, y: u32) -> bool {
+        let center = self.brightness[self.index(x, y)];
+        let mut equal = usize::from(self.on_edge(x, y));
+        let (mut darkest, mut brightest) = ((0.0, None), (0.0, None));
+        for (nx, ny) in self.neighbours(x, y) {
+            let delta = self.brightness[self.index(nx, ny)] - center;
+            if delta == 0.0 {
+                equal += 1;
+                if equal > 2 {
+                    return false;
+                }
+            } else if delta < darkest.0 {
+                darkest = (delta, Some((nx, ny)));
+            } else if delta > brightest.0 {
+                brightest = (delta, Some((nx, ny)));
+            }
+        }
+        match (darkest.1, brightest.1) {
+            (Some(d), Some(b)) => [d, b]
+                .iter()
+                .any(|&(nx, ny)| self.has_many_siblings(nx, ny) && other.has_many_siblings(nx, ny)),
+            _ => false,
+        }
+    }
+}
+
+/// Find the pixels that differ between the images by more than `tolerance` (see
+/// `count_diff_pixels`) but look like anti-aliased edge pixels in either image.
+///
+/// The result is a mask ignoring exactly those pixels, so it can take them out of
+/// the comparison and mark them on the diff image.
+pub fn find_antialiased(image1: &DynamicImage, image2: &DynamicImage, tolerance: u8) -> Mask {
+    let (width, height) = image1.dimensions();
+    let changed = changed_pixels(image1, image2, tolerance, None);
+    let (pixels1, pixels2) = (Pixels::of(image1), Pixels::of(image2));
+    let antialiased = changed
+        .iter()
+        .enumerate()
+        .map(|(i, &changed)| {
+            let (x, y) = ((i % width as usize) as u32, (i / width as usize) as u32);
+            changed
+                && (pixels1.is_antialiased(&pixels2, x, y)
+                    || pixels2.is_antialiased(&pixels1, x, y))
+        })
+        .collect();
+    Mask::from_flags(width, height, antialiased)
+}
+
+#[test]
+fn test_find_antialiased() {
+    // A vertical edge from black to white, smoothed by a gray column that is a
+    // different shade in the second image, which also has one really changed pixel
+    let edge = |gray: u8| {
+        image::GrayImage::from_fn(5, 5, |x, _| match x {
+            0 | 1 => image::Luma([0]),
+            2 => image::Luma([gray]),
+            _ => image::Luma([255]),
+        })
+    };
+    let image1 = DynamicImage::ImageLuma8(edge(128));
+    let mut image2 = edge(100);
+    image2.put_pixel(0, 2, image::Luma([255]));
+    let image2 = DynamicImage::ImageLuma8(image2);
+
+    let antialiased = find_antialiased(&image1, &image2, 0);
+    assert_eq!(antialiased.ignored_count(), 5);
+    assert!((0..5).all(|y| antialiased.is_ignored(y * 5 + 2)));
+    assert!(!antialiased.is_ignored(2 * 5));
+}
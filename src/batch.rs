@@ -0,0 +1,244 @@
+//! Comparing two directories of images, pairing the files by their path relative
+//! to each directory.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use image::ImageFormat;
+use serde::Serialize;
+
+use crate::{compare_writing, Config, DiffError, OutputFormat, Report, Verdict, WriteDiff};
+
+/// What happened to a file between the first and the second directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileStatus {
+    /// Only in the second directory
+    Added,
+    /// Only in the first directory
+    Removed,
+    /// In both, and the images differ by more than the configured limits
+    Changed,
+    /// In both, and the images are within the configured limits
+    Unchanged,
+    /// In both, but the images could not be compared
+    Error,
+}
+
+/// The outcome for one relative path
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BatchEntry {
+    /// The path relative to both directories
+    pub path: String,
+    pub status: FileStatus,
+    /// The comparison of a file in both directories, or why it failed
+    pub report: Option<Report>,
+}
+
+/// The outcome for every file in either directory, ordered by path
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BatchReport {
+    pub entries: Vec<BatchEntry>,
+}
+
+impl BatchReport {
+    /// How many entries have the given status
+    pub fn count(&self, status: FileStatus) -> usize {
+        self.entries.iter().filter(|e| e.status == status).count()
+    }
+
+    /// Pass when every file is in both directories and unchanged
+    pub fn verdict(&self) -> Verdict {
+        if self.count(FileStatus::Unchanged) == self.entries.len() {
+            Verdict::Pass
+        } else {
+            Verdict::Fail
+        }
+    }
+
+    /// The report as a JSON object
+    pub fn to_json(&self) -> String {
+        // unwrap() is safe: every field serializes, and all map keys are strings
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// Print one line per file, followed by the totals
+    pub fn print_text(&self) {
+        for entry in &self.entries {
+            let status = match entry.status {
+                FileStatus::Added => "added",
+                FileStatus::Removed => "removed",
+                FileStatus::Changed => "changed",
+                FileStatus::Unchanged => "unchanged",
+                FileStatus::Error => "error",
+            };
+            let error = entry
+                .report
+                .as_ref()
+                .and_then(|r| r.error.as_ref())
+                .map(|e| format!(" ({})", e.message))
+                .unwrap_or_default();
+            println!("{}: {}{}", status, entry.path, error);
+        }
+        println!(
+            "{} files: {} changed, {} added, {} removed, {} unchanged, {} errors",
+            self.entries.len(),
+            self.count(FileStatus::Changed),
+            self.count(FileStatus::Added),
+            self.count(FileStatus::Removed),
+            self.count(FileStatus::Unchanged),
+            self.count(FileStatus::Error)
+        );
+    }
+}
+
+/// The paths of all image files below `dir`, relative to it. Files are recognized
+/// as images by their extension. Links to directories are not followed, so that a
+/// link to a parent directory can't make the listing endless.
+fn list_images(dir: &Path) -> Result<BTreeSet<PathBuf>, DiffError> {
+    let list_error = |source| DiffError::ListDir {
+        path: dir.to_string_lossy().into_owned(),
+        source,
+    };
+    let mut images = BTreeSet::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(subdir) = pending.pop() {
+        for entry in fs::read_dir(dir.join(&subdir)).map_err(list_error)? {
+            let entry = entry.map_err(list_error)?;
+            let path = subdir.join(entry.file_name());
+            if entry.file_type().map_err(list_error)?.is_dir() {
+                pending.push(path);
+            } else if ImageFormat::from_path(&path).is_ok() {
+                images.insert(path);
+            }
+        }
+    }
+    Ok(images)
+}
+
+/// Compare the file at `path` below both directories of the configuration. A
+/// changed file's diff image is written to the same path below the output
+/// directory, if one is configured.
+fn compare_pair(config: &Config, path: &Path) -> BatchEntry {
+    let image1 = Path::new(config.image1).join(path);
+    let image2 = Path::new(config.image2).join(path);
+    let (image1, image2) = (image1.to_string_lossy(), image2.to_string_lossy());
+    let diff = config.filename.map(|dir| Path::new(dir).join(path));
+    let diff = diff.as_ref().map(|diff| diff.to_string_lossy());
+    let mut pair = Config {
+        image1: &image1,
+        image2: &image2,
+        filename: diff.as_deref(),
+        // The other outputs are single files, which every pair would overwrite;
+        // the command line rejects them in directory mode
+        blink: None,
+        onion_skin: None,
+        boxes_image: None,
+        html_report: None,
+        ..config.clone()
+    };
+    // Without limits, any difference counts as a change
+    if pair.threshold.is_none() && pair.max_pixels.is_none() {
+        pair.max_pixels = Some(0);
+    }
+
+    let (status, report) = match compare_writing(&pair, WriteDiff::IfFailed) {
+        Ok(report) if report.verdict == Some(Verdict::Fail) => (FileStatus::Changed, report),
+        Ok(report) => (FileStatus::Unchanged, report),
+        Err(err) => (
+            FileStatus::Error,
+            Report::failed(&image1, &image2, config.metric, &err),
+        ),
+    };
+    BatchEntry {
+        path: path.to_string_lossy().into_owned(),
+        status,
+        report: Some(report),
+    }
+}
+
+/// Compare the files at `paths` on `jobs` threads, returning the entries in the
+/// order of `paths` whatever order they finish in. Each thread compares one pair
+/// at a time, so at most `jobs` pairs of images are decoded at once.
+fn compare_pairs(config: &Config, paths: &[&PathBuf], jobs: usize) -> Vec<BatchEntry> {
+    let next = AtomicUsize::new(0);
+    let finished = Mutex::new(Vec::with_capacity(paths.len()));
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, paths.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = paths.get(index) else {
+                    break;
+                };
+                let entry = compare_pair(config, path);
+                // unwrap() is safe: the lock is only poisoned if another thread
+                // panicked, which takes the whole scope down anyway
+                finished.lock().unwrap().push((index, entry));
+            });
+        }
+    });
+    let mut entries = finished.into_inner().unwrap();
+    entries.sort_by_key(|&(index, _)| index);
+    entries.into_iter().map(|(_, entry)| entry).collect()
+}
+
+/// The number of threads to compare on unless another is given: one per CPU
+pub fn default_jobs() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Compare every image below the directory `config.image1` with the image at the
+/// same relative path below `config.image2`. `config.filename`, if given, is the
+/// directory the diff images of changed files are written to, mirroring the
+/// inputs.
+///
+/// Pairs are compared on `config.jobs` threads; the entries are ordered by path
+/// either way. Only failing to list a directory or to create the output directory
+/// is an error; files that cannot be compared are reported with `FileStatus::Error`.
+pub fn compare_dirs(config: &Config) -> Result<BatchReport, DiffError> {
+    let images1 = list_images(Path::new(config.image1))?;
+    let images2 = list_images(Path::new(config.image2))?;
+    if let Some(dir) = config.filename {
+        fs::create_dir_all(dir).map_err(|source| DiffError::CreateDir {
+            path: dir.to_string(),
+            source,
+        })?;
+    }
+    let jobs = config.jobs.unwrap_or_else(default_jobs);
+    let in_both: Vec<&PathBuf> = images1.intersection(&images2).collect();
+    // Both sets iterate in order, so the compared files come up in the union in
+    // the order they were compared in
+    let mut compared = compare_pairs(config, &in_both, jobs).into_iter();
+    let entries = images1
+        .union(&images2)
+        .map(|path| {
+            let entry = |status| BatchEntry {
+                path: path.to_string_lossy().into_owned(),
+                status,
+                report: None,
+            };
+            match (images1.contains(path), images2.contains(path)) {
+                // unwrap() is safe: there is one compared entry per file in both
+                (true, true) => compared.next().unwrap(),
+                (true, false) => entry(FileStatus::Removed),
+                _ => entry(FileStatus::Added),
+            }
+        })
+        .collect();
+    Ok(BatchReport { entries })
+}
+
+/// Compare two directories as configured, see `compare_dirs`, and print the
+/// results in the configured format
+pub fn run_batch(config: Config) -> Result<Verdict, DiffError> {
+    let report = compare_dirs(&config)?;
+    match config.format {
+        OutputFormat::Text => report.print_text(),
+        OutputFormat::Json => println!("{}", report.to_json()),
+    }
+    Ok(report.verdict())
+}
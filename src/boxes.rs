@@ -0,0 +1,265 @@
+//! Finding the areas that changed between two images, as bounding boxes around
+//! connected groups of differing pixels.
+
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use serde::Serialize;
+
+use crate::depth::{max_channel_value, Samples};
+use crate::report::serialize_number;
+use crate::{changed_pixels, par, Mask, Rect};
+
+/// How far apart (in pixels) two changed areas may be and still be merged into one
+pub const DEFAULT_MERGE_DISTANCE: u32 = 5;
+
+/// The color bounding boxes are drawn in
+pub const BOX_COLOR: Rgba<u8> = Rgba([255, 0, 0, 255]);
+
+/// The size of the grid cells used to find the boxes near another one
+const CELL_SIZE: u32 = 64;
+
+/// A rectangle around a changed area of the images
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ChangeBox {
+    pub rect: Rect,
+    /// The number of differing pixels within the box
+    pub pixels: u64,
+    /// The mean absolute difference of the differing pixels, averaged over the
+    /// channels and relative to the largest channel value, from 0 to 1
+    #[serde(serialize_with = "serialize_number")]
+    pub mean_intensity: f64,
+}
+
+impl ChangeBox {
+    fn x_end(&self) -> u32 {
+        self.rect.x + self.rect.width
+    }
+
+    fn y_end(&self) -> u32 {
+        self.rect.y + self.rect.height
+    }
+
+    /// Whether the gap between the two boxes is at most `distance` pixels in both
+    /// directions
+    fn is_near(&self, other: &ChangeBox, distance: u32) -> bool {
+        let gap = |start1: u32, end1: u32, start2: u32, end2: u32| {
+            start2.saturating_sub(end1).max(start1.saturating_sub(end2))
+        };
+        gap(self.rect.x, self.x_end(), other.rect.x, other.x_end()) <= distance
+            && gap(self.rect.y, self.y_end(), other.rect.y, other.y_end()) <= distance
+    }
+
+    fn merge(&self, other: &ChangeBox) -> ChangeBox {
+        let x = self.rect.x.min(other.rect.x);
+        let y = self.rect.y.min(other.rect.y);
+        let pixels = self.pixels + other.pixels;
+        ChangeBox {
+            rect: Rect {
+                x,
+                y,
+                width: self.x_end().max(other.x_end()) - x,
+                height: self.y_end().max(other.y_end()) - y,
+            },
+            pixels,
+            mean_intensity: (self.mean_intensity * self.pixels as f64
+                + other.mean_intensity * other.pixels as f64)
+                / pixels as f64,
+        }
+    }
+}
+
+/// Return the bounding boxes of the changed areas of two images, ordered top to
+/// bottom, then left to right.
+///
+/// A pixel has changed if a channel differs by more than `tolerance` (on the
+/// 8-bit scale, like `count_diff_pixels`), unless the mask ignores it. Changed
+/// pixels that touch, including diagonally, form one area; areas whose boxes are
+/// at most `merge_distance` pixels apart are merged.
+pub fn find_changed_boxes(
+    image1: &DynamicImage,
+    image2: &DynamicImage,
+    tolerance: u8,
+    merge_distance: u32,
+    mask: Option<&Mask>,
+) -> Vec<ChangeBox> {
+    let (width, height) = image1.dimensions();
+    let max_val = max_channel_value(image1, image2);
+    let channels = usize::from(image1.color().channel_count());
+    let pixel_count = width as usize * height as usize;
+
+    let changed = changed_pixels(image1, image2, tolerance, mask);
+    // The sum of the channel differences of every pixel
+    let (samples1, samples2) = (Samples::of(image1), Samples::of(image2));
+    let intensity = par::map_ranges(samples1.len(), par::row_len(image1), |range| {
+        let mut intensity = vec![0.0; range.len() / channels];
+        samples1
+            .slice(range.clone())
+            .for_each_pair(&samples2.slice(range), |i, p1, p2| {
+                intensity[i / channels] += (p1 - p2).abs();
+            });
+        intensity
+    })
+    .concat();
+
+    let mut boxes = Vec::new();
+    let mut visited = vec![false; pixel_count];
+    let mut stack = Vec::new();
+    for start in 0..pixel_count {
+        if !changed[start] || visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        stack.push(start);
+        let (mut x0, mut y0, mut x1, mut y1) = (width, height, 0, 0);
+        let mut pixels = 0;
+        let mut intensity_sum = 0.0;
+        while let Some(i) = stack.pop() {
+            let (x, y) = ((i % width as usize) as u32, (i / width as usize) as u32);
+            x0 = x0.min(x);
+            y0 = y0.min(y);
+            x1 = x1.max(x);
+            y1 = y1.max(y);
+            pixels += 1;
+            intensity_sum += intensity[i];
+            for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                    let n = ny as usize * width as usize + nx as usize;
+                    if changed[n] && !visited[n] {
+                        visited[n] = true;
+                        stack.push(n);
+                    }
+                }
+            }
+        }
+        boxes.push(ChangeBox {
+            rect: Rect {
+                x: x0,
+                y: y0,
+                width: x1 - x0 + 1,
+                height: y1 - y0 + 1,
+            },
+            pixels,
+            mean_intensity: intensity_sum / (pixels as f64 * channels as f64 * max_val),
+        });
+    }
+
+    let mut boxes = merge_nearby(boxes, merge_distance, width, height);
+    boxes.sort_by_key(|b| (b.rect.y, b.rect.x));
+    boxes
+}
+
+/// Merge boxes (within an image of the given size) until none are at most
+/// `distance` apart.
+///
+/// Merging can bring a box near one it was not near before, so a box that grew
+/// is checked again. Every box is listed in the grid cells within `distance` of
+/// it, so a check only looks at the boxes listed in the cells the box covers.
+fn merge_nearby(
+    mut boxes: Vec<ChangeBox>,
+    distance: u32,
+    width: u32,
+    height: u32,
+) -> Vec<ChangeBox> {
+    if boxes.is_empty() {
+        return boxes;
+    }
+    let columns = width.div_ceil(CELL_SIZE) as usize;
+    let rows = height.div_ceil(CELL_SIZE) as usize;
+    // The cells covered by a box grown by `grow` pixels on each side
+    let cells = |b: &ChangeBox, grow: u32| {
+        let x0 = b.rect.x.saturating_sub(grow) / CELL_SIZE;
+        let y0 = b.rect.y.saturating_sub(grow) / CELL_SIZE;
+        let x1 = (b.x_end() - 1).saturating_add(grow).min(width - 1) / CELL_SIZE;
+        let y1 = (b.y_end() - 1).saturating_add(grow).min(height - 1) / CELL_SIZE;
+        (y0..=y1).flat_map(move |y| (x0..=x1).map(move |x| y as usize * columns + x as usize))
+    };
+
+    let mut grid = vec![Vec::new(); columns * rows];
+    for (i, b) in boxes.iter().enumerate() {
+        for cell in cells(b, distance) {
+            grid[cell].push(i);
+        }
+    }
+    let mut merged = vec![false; boxes.len()];
+    for i in 0..boxes.len() {
+        if merged[i] {
+            continue;
+        }
+        loop {
+            let mut grown = false;
+            for cell in cells(&boxes[i], 0) {
+                for &j in &grid[cell] {
+                    if j != i && !merged[j] && boxes[i].is_near(&boxes[j], distance) {
+                        boxes[i] = boxes[i].merge(&boxes[j]);
+                        merged[j] = true;
+                        grown = true;
+                    }
+                }
+            }
+            if !grown {
+                break;
+            }
+            for cell in cells(&boxes[i], distance) {
+                grid[cell].push(i);
+            }
+        }
+    }
+
+    boxes
+        .into_iter()
+        .zip(merged)
+        .filter(|&(_, merged)| !merged)
+        .map(|(b, _)| b)
+        .collect()
+}
+
+/// Return an RGBA copy of `image` with the outline of every box drawn over it
+pub fn draw_boxes(image: &DynamicImage, boxes: &[ChangeBox]) -> RgbaImage {
+    let mut out = image.to_rgba8();
+    for b in boxes {
+        let (x_end, y_end) = (b.x_end() - 1, b.y_end() - 1);
+        for x in b.rect.x..=x_end {
+            out.put_pixel(x, b.rect.y, BOX_COLOR);
+            out.put_pixel(x, y_end, BOX_COLOR);
+        }
+        for y in b.rect.y..=y_end {
+            out.put_pixel(b.rect.x, y, BOX_COLOR);
+            out.put_pixel(x_end, y, BOX_COLOR);
+        }
+    }
+    out
+}
+
+#[test]
+fn test_merge_nearby_boxes() {
+    let boxed = |x, y, pixels, mean_intensity| ChangeBox {
+        rect: Rect {
+            x,
+            y,
+            width: 2,
+            height: 2,
+        },
+        pixels,
+        mean_intensity,
+    };
+    let a = boxed(0, 0, 1, 1.0);
+    let b = boxed(5, 0, 3, 0.2);
+    // There are 3 pixels between them
+    assert!(!a.is_near(&b, 2));
+    assert!(a.is_near(&b, 3) && b.is_near(&a, 3));
+    let merged = a.merge(&b);
+    assert_eq!(merged.rect, "0,0,7,2".parse().unwrap());
+    assert_eq!(merged.pixels, 4);
+    assert!((merged.mean_intensity - 0.4).abs() < 1e-12);
+
+    // c is only near the box that d and e merge into
+    let (d, e) = (boxed(0, 0, 1, 1.0), boxed(3, 3, 1, 1.0));
+    let c = ChangeBox {
+        rect: "0,6,1,1".parse().unwrap(),
+        pixels: 1,
+        mean_intensity: 1.0,
+    };
+    assert!(d.is_near(&e, 1) && !d.is_near(&c, 1) && !e.is_near(&c, 1));
+    let boxes = merge_nearby(vec![c, d, e], 1, 10, 10);
+    assert_eq!(boxes.len(), 1);
+    assert_eq!(boxes[0].rect, "0,0,5,7".parse().unwrap());
+}
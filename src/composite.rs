@@ -0,0 +1,113 @@
+//! Laying out the inputs and the diff next to each other in one labelled image.
+
+use std::str::FromStr;
+
+use image::{imageops, DynamicImage, Rgba, RgbaImage};
+
+use crate::text::{draw_text, text_height, text_width};
+
+/// Space between and around the panels of a composite unless another is given
+pub const DEFAULT_GUTTER: u32 = 10;
+
+/// Largest gutter accepted, which keeps the size of a composite from overflowing
+pub const MAX_GUTTER: u32 = 1000;
+
+const BACKGROUND: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const LABEL_COLOR: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+/// Which way the panels of a composite are laid out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    /// Next to each other, left to right
+    #[default]
+    Horizontal,
+    /// Above each other, top to bottom
+    Vertical,
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Layout, String> {
+        match s {
+            "horizontal" => Ok(Layout::Horizontal),
+            "vertical" => Ok(Layout::Vertical),
+            _ => Err(format!("unknown layout \"{}\"", s)),
+        }
+    }
+}
+
+/// How to write a composite of the inputs and the diff
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Composite {
+    pub layout: Layout,
+    /// Whether to add the diff as a third panel, or only show the inputs
+    pub include_diff: bool,
+    pub gutter: u32,
+}
+
+/// An image with the label shown above it
+pub struct Panel<'a> {
+    pub image: &'a DynamicImage,
+    pub label: String,
+}
+
+/// Shorten `label` with a trailing "..." so it is at most `width` pixels wide
+fn fit_label(label: &str, width: u32, scale: u32) -> String {
+    if text_width(label, scale) <= width {
+        return label.to_string();
+    }
+    let mut fitted: String = label.to_string();
+    while !fitted.is_empty() && text_width(&format!("{}...", fitted), scale) > width {
+        fitted.pop();
+    }
+    format!("{}...", fitted)
+}
+
+/// Lay out the panels in one image with `gutter` pixels between and around them.
+///
+/// Every panel gets a cell the size of the largest one, so images of different
+/// sizes line up; smaller images sit at the top-left of their cell. Transparent
+/// pixels show the white background. `gutter` should be at most `MAX_GUTTER`.
+pub fn composite(panels: &[Panel], layout: Layout, gutter: u32) -> RgbaImage {
+    let cell_width = panels.iter().map(|p| p.image.width()).max().unwrap_or(0);
+    let cell_height = panels.iter().map(|p| p.image.height()).max().unwrap_or(0);
+    // Larger text on larger images
+    let scale = if cell_width >= 400 { 2 } else { 1 };
+    let label_height = text_height(scale) + gutter / 2;
+    let slot_height = label_height + cell_height;
+
+    let count = panels.len() as u32;
+    let (width, height) = match layout {
+        Layout::Horizontal => (
+            count * cell_width + (count + 1) * gutter,
+            slot_height + 2 * gutter,
+        ),
+        Layout::Vertical => (
+            cell_width + 2 * gutter,
+            count * slot_height + (count + 1) * gutter,
+        ),
+    };
+
+    let mut out = RgbaImage::from_pixel(width, height, BACKGROUND);
+    for (n, panel) in panels.iter().enumerate() {
+        let n = n as u32;
+        let (x, y) = match layout {
+            Layout::Horizontal => (gutter + n * (cell_width + gutter), gutter),
+            Layout::Vertical => (gutter, gutter + n * (slot_height + gutter)),
+        };
+        let label = fit_label(&panel.label, cell_width, scale);
+        draw_text(&mut out, x, y, &label, LABEL_COLOR, scale);
+        let image = panel.image.to_rgba8();
+        imageops::overlay(&mut out, &image, i64::from(x), i64::from(y + label_height));
+    }
+    out
+}
+
+#[test]
+fn test_fit_label() {
+    assert_eq!(fit_label("abc", 100, 1), "abc");
+    // Each character is 6 pixels wide including the space after it
+    assert_eq!(fit_label("abcdefghij", 35, 1), "abc...");
+    assert_eq!(fit_label("abcdefghij", 10, 1), "...");
+}
@@ -0,0 +1,326 @@
+//! Perceptual color difference (ΔE) in CIE L*a*b* space.
+//!
+//! Pixels are assumed to be sRGB with a D65 white point. Alpha is ignored and
+//! grayscale images are compared as if they were RGB. 16-bit and floating-point
+//! images are converted at full precision; HDR values are scaled down so the
+//! brightest channel of either image is white.
+
+use std::f64::consts::PI;
+
+use image::{DynamicImage, GrayImage, Luma};
+
+use crate::depth::{float_max, is_8bit};
+use crate::{par, Mask};
+
+/// The ΔE that is rendered as white in a ΔE image; black vs white is 100 in CIE76
+pub(crate) const VISUAL_MAX: f64 = 100.0;
+
+/// D65 reference white
+const WHITE_X: f64 = 0.95047;
+const WHITE_Y: f64 = 1.0;
+const WHITE_Z: f64 = 1.08883;
+
+/// A color in CIE L*a*b* space
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Lab {
+    pub l: f64,
+    pub a: f64,
+    pub b: f64,
+}
+
+/// The formula used to compute ΔE between two Lab colors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeltaEFormula {
+    Cie76,
+    Cie94,
+    Ciede2000,
+}
+
+impl DeltaEFormula {
+    pub fn delta_e(self, lab1: Lab, lab2: Lab) -> f64 {
+        match self {
+            DeltaEFormula::Cie76 => delta_e76(lab1, lab2),
+            DeltaEFormula::Cie94 => delta_e94(lab1, lab2),
+            DeltaEFormula::Ciede2000 => delta_e2000(lab1, lab2),
+        }
+    }
+}
+
+/// Undo the sRGB transfer function of a channel value in [0, 1]
+fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn lab_f(t: f64) -> f64 {
+    let delta: f64 = 6.0 / 29.0;
+    if t > delta.powi(3) {
+        t.cbrt()
+    } else {
+        t / (3.0 * delta * delta) + 4.0 / 29.0
+    }
+}
+
+/// Convert an 8-bit sRGB color to CIE L*a*b*
+pub fn srgb_to_lab(rgb: [u8; 3]) -> Lab {
+    srgb_float_to_lab([
+        f64::from(rgb[0]) / 255.0,
+        f64::from(rgb[1]) / 255.0,
+        f64::from(rgb[2]) / 255.0,
+    ])
+}
+
+/// Convert an sRGB color with channels in [0, 1] to CIE L*a*b*
+pub fn srgb_float_to_lab(rgb: [f64; 3]) -> Lab {
+    let r = srgb_to_linear(rgb[0]);
+    let g = srgb_to_linear(rgb[1]);
+    let b = srgb_to_linear(rgb[2]);
+
+    let x = 0.412_456_4 * r + 0.357_576_1 * g + 0.180_437_5 * b;
+    let y = 0.212_672_9 * r + 0.715_152_2 * g + 0.072_175_0 * b;
+    let z = 0.019_333_9 * r + 0.119_192_0 * g + 0.950_304_1 * b;
+
+    let fx = lab_f(x / WHITE_X);
+    let fy = lab_f(y / WHITE_Y);
+    let fz = lab_f(z / WHITE_Z);
+
+    Lab {
+        l: 116.0 * fy - 16.0,
+        a: 500.0 * (fx - fy),
+        b: 200.0 * (fy - fz),
+    }
+}
+
+/// CIE76: euclidean distance in Lab
+pub fn delta_e76(lab1: Lab, lab2: Lab) -> f64 {
+    ((lab1.l - lab2.l).powi(2) + (lab1.a - lab2.a).powi(2) + (lab1.b - lab2.b).powi(2)).sqrt()
+}
+
+/// CIE94 with the graphic arts weighting factors
+pub fn delta_e94(lab1: Lab, lab2: Lab) -> f64 {
+    let (k1, k2) = (0.045, 0.015);
+    let c1 = lab1.a.hypot(lab1.b);
+    let c2 = lab2.a.hypot(lab2.b);
+    let dl = lab1.l - lab2.l;
+    let dc = c1 - c2;
+    let da = lab1.a - lab2.a;
+    let db = lab1.b - lab2.b;
+    // Rounding can make this slightly negative for near-identical colors
+    let dh2 = (da * da + db * db - dc * dc).max(0.0);
+
+    let sc = 1.0 + k1 * c1;
+    let sh = 1.0 + k2 * c1;
+    (dl * dl + (dc / sc).powi(2) + dh2 / (sh * sh)).sqrt()
+}
+
+/// CIEDE2000, following Sharma, Wu and Dalal (2005)
+pub fn delta_e2000(lab1: Lab, lab2: Lab) -> f64 {
+    let c1 = lab1.a.hypot(lab1.b);
+    let c2 = lab2.a.hypot(lab2.b);
+    let c_bar7 = ((c1 + c2) / 2.0).powi(7);
+    let g = 0.5 * (1.0 - (c_bar7 / (c_bar7 + 25f64.powi(7))).sqrt());
+
+    let a1 = (1.0 + g) * lab1.a;
+    let a2 = (1.0 + g) * lab2.a;
+    let c1 = a1.hypot(lab1.b);
+    let c2 = a2.hypot(lab2.b);
+    let hue = |b: f64, a: f64| {
+        if a == 0.0 && b == 0.0 {
+            0.0
+        } else {
+            let h = b.atan2(a).to_degrees();
+            if h < 0.0 {
+                h + 360.0
+            } else {
+                h
+            }
+        }
+    };
+    let h1 = hue(lab1.b, a1);
+    let h2 = hue(lab2.b, a2);
+
+    let dl = lab2.l - lab1.l;
+    let dc = c2 - c1;
+    let dh = if c1 * c2 == 0.0 {
+        0.0
+    } else if (h2 - h1).abs() <= 180.0 {
+        h2 - h1
+    } else if h2 - h1 > 180.0 {
+        h2 - h1 - 360.0
+    } else {
+        h2 - h1 + 360.0
+    };
+    let dh = 2.0 * (c1 * c2).sqrt() * (dh / 2.0).to_radians().sin();
+
+    let l_bar = (lab1.l + lab2.l) / 2.0;
+    let c_bar = (c1 + c2) / 2.0;
+    let h_bar = if c1 * c2 == 0.0 {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180.0 {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 360.0 {
+        (h1 + h2 + 360.0) / 2.0
+    } else {
+        (h1 + h2 - 360.0) / 2.0
+    };
+
+    let t = 1.0 - 0.17 * (h_bar - 30.0).to_radians().cos()
+        + 0.24 * (2.0 * h_bar).to_radians().cos()
+        + 0.32 * (3.0 * h_bar + 6.0).to_radians().cos()
+        - 0.20 * (4.0 * h_bar - 63.0).to_radians().cos();
+    let d_theta = 30.0 * (-((h_bar - 275.0) / 25.0).powi(2)).exp();
+    let c_bar7 = c_bar.powi(7);
+    let rc = 2.0 * (c_bar7 / (c_bar7 + 25f64.powi(7))).sqrt();
+    let l_term = (l_bar - 50.0).powi(2);
+    let sl = 1.0 + 0.015 * l_term / (20.0 + l_term).sqrt();
+    let sc = 1.0 + 0.045 * c_bar;
+    let sh = 1.0 + 0.015 * c_bar * t;
+    let rt = -(2.0 * d_theta * PI / 180.0).sin() * rc;
+
+    ((dl / sl).powi(2) + (dc / sc).powi(2) + (dh / sh).powi(2) + rt * (dc / sc) * (dh / sh)).sqrt()
+}
+
+/// Per-pixel ΔE values for a pair of images, in row-major order
+#[derive(Debug, Clone)]
+pub struct DeltaEMap {
+    pub width: u32,
+    pub height: u32,
+    pub values: Vec<f64>,
+}
+
+impl DeltaEMap {
+    /// The mean ΔE over the whole image
+    pub fn mean(&self) -> f64 {
+        self.values.iter().sum::<f64>() / self.values.len() as f64
+    }
+
+    /// The mean ΔE over the pixels the mask does not ignore, 0 if it ignores them
+    /// all
+    pub fn masked_mean(&self, mask: &Mask) -> f64 {
+        let included = mask.included_count();
+        if included == 0 {
+            return 0.0;
+        }
+        let sum: f64 = self
+            .values
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| !mask.is_ignored(i))
+            .map(|(_, v)| v)
+            .sum();
+        sum / included as f64
+    }
+
+    /// The number of pixels whose ΔE is above `tolerance`
+    pub fn count_above(&self, tolerance: f64) -> u64 {
+        self.values.iter().filter(|&&v| v > tolerance).count() as u64
+    }
+
+    /// Like `count_above`, but pixels the mask ignores are never counted
+    pub fn count_masked_above(&self, tolerance: f64, mask: &Mask) -> u64 {
+        self.values
+            .iter()
+            .enumerate()
+            .filter(|&(i, &v)| v > tolerance && !mask.is_ignored(i))
+            .count() as u64
+    }
+
+    /// For every pixel, whether its ΔE is above `tolerance`
+    pub fn changed(&self, tolerance: f64) -> Vec<bool> {
+        self.values.iter().map(|&v| v > tolerance).collect()
+    }
+
+    /// Render the map as a grayscale image where brightness is proportional to ΔE
+    pub fn to_image(&self) -> GrayImage {
+        GrayImage::from_fn(self.width, self.height, |x, y| {
+            let value = self.values[(y * self.width + x) as usize];
+            Luma([((value / VISUAL_MAX).min(1.0) * 255.0).round() as u8])
+        })
+    }
+}
+
+/// Return the per-pixel ΔE of the two images using the given formula
+pub fn delta_e_map(
+    image1: &DynamicImage,
+    image2: &DynamicImage,
+    formula: DeltaEFormula,
+) -> DeltaEMap {
+    let (width, height) = (image1.width(), image1.height());
+    // One row of RGB values at a time
+    let row = width as usize * 3;
+    let rows = if is_8bit(image1) {
+        let rgb1 = image1.to_rgb8().into_raw();
+        let rgb2 = image2.to_rgb8().into_raw();
+        par::map_ranges(rgb1.len(), row, |range| {
+            rgb1[range.clone()]
+                .chunks_exact(3)
+                .zip(rgb2[range].chunks_exact(3))
+                .map(|(p1, p2)| {
+                    let lab = |p: &[u8]| srgb_to_lab([p[0], p[1], p[2]]);
+                    formula.delta_e(lab(p1), lab(p2))
+                })
+                .collect::<Vec<_>>()
+        })
+    } else {
+        let max = float_max(image1, image2);
+        let lab = |p: &[f32]| {
+            let c = |v: f32| (f64::from(v) / max).clamp(0.0, 1.0);
+            srgb_float_to_lab([c(p[0]), c(p[1]), c(p[2])])
+        };
+        let rgb1 = image1.to_rgb32f().into_raw();
+        let rgb2 = image2.to_rgb32f().into_raw();
+        par::map_ranges(rgb1.len(), row, |range| {
+            rgb1[range.clone()]
+                .chunks_exact(3)
+                .zip(rgb2[range].chunks_exact(3))
+                .map(|(p1, p2)| formula.delta_e(lab(p1), lab(p2)))
+                .collect::<Vec<_>>()
+        })
+    };
+    let values = rows.concat();
+
+    DeltaEMap {
+        width,
+        height,
+        values,
+    }
+}
+
+#[test]
+fn test_srgb_to_lab() {
+    let white = srgb_to_lab([255, 255, 255]);
+    assert!((white.l - 100.0).abs() < 1e-3);
+    assert!(white.a.abs() < 1e-2 && white.b.abs() < 1e-2);
+    let black = srgb_to_lab([0, 0, 0]);
+    assert_eq!(black.l, 0.0);
+}
+
+#[test]
+fn test_delta_e_formulas() {
+    // Pair 1 from the CIEDE2000 test data of Sharma et al.
+    let lab1 = Lab {
+        l: 50.0,
+        a: 2.6772,
+        b: -79.7751,
+    };
+    let lab2 = Lab {
+        l: 50.0,
+        a: 0.0,
+        b: -82.7485,
+    };
+    assert!((delta_e2000(lab1, lab2) - 2.0425).abs() < 1e-4);
+    assert!((delta_e76(lab1, lab2) - 4.0011).abs() < 1e-4);
+    assert!((delta_e94(lab1, lab2) - 1.3950).abs() < 1e-4);
+    for formula in [
+        DeltaEFormula::Cie76,
+        DeltaEFormula::Cie94,
+        DeltaEFormula::Ciede2000,
+    ]
+    .iter()
+    {
+        assert_eq!(formula.delta_e(lab1, lab1), 0.0);
+    }
+}
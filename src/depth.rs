@@ -0,0 +1,187 @@
+//! Access to channel values at an image's native bit depth (8-bit, 16-bit or f32).
+
+use std::ops::Range;
+use std::str::FromStr;
+
+use image::{DynamicImage, GenericImageView, ImageBuffer};
+
+/// The channel values of an image, borrowed at their native depth
+pub(crate) enum Samples<'a> {
+    U8(&'a [u8]),
+    U16(&'a [u16]),
+    F32(&'a [f32]),
+}
+
+impl<'a> Samples<'a> {
+    pub(crate) fn of(image: &'a DynamicImage) -> Samples<'a> {
+        match image {
+            DynamicImage::ImageLuma8(b) => Samples::U8(b.as_raw()),
+            DynamicImage::ImageLumaA8(b) => Samples::U8(b.as_raw()),
+            DynamicImage::ImageRgb8(b) => Samples::U8(b.as_raw()),
+            DynamicImage::ImageRgba8(b) => Samples::U8(b.as_raw()),
+            DynamicImage::ImageLuma16(b) => Samples::U16(b.as_raw()),
+            DynamicImage::ImageLumaA16(b) => Samples::U16(b.as_raw()),
+            DynamicImage::ImageRgb16(b) => Samples::U16(b.as_raw()),
+            DynamicImage::ImageRgba16(b) => Samples::U16(b.as_raw()),
+            DynamicImage::ImageRgb32F(b) => Samples::F32(b.as_raw()),
+            DynamicImage::ImageRgba32F(b) => Samples::F32(b.as_raw()),
+            _ => unreachable!("unknown color type {:?}", image.color()),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Samples::U8(s) => s.len(),
+            Samples::U16(s) => s.len(),
+            Samples::F32(s) => s.len(),
+        }
+    }
+
+    /// The values at `range` of indices
+    pub(crate) fn slice(&self, range: Range<usize>) -> Samples<'a> {
+        match self {
+            Samples::U8(s) => Samples::U8(&s[range]),
+            Samples::U16(s) => Samples::U16(&s[range]),
+            Samples::F32(s) => Samples::F32(&s[range]),
+        }
+    }
+
+    pub(crate) fn get(&self, i: usize) -> f64 {
+        match self {
+            Samples::U8(s) => f64::from(s[i]),
+            Samples::U16(s) => f64::from(s[i]),
+            Samples::F32(s) => f64::from(s[i]),
+        }
+    }
+
+    /// The largest channel value, or 0 for an empty image
+    fn max(&self) -> f64 {
+        (0..self.len()).map(|i| self.get(i)).fold(0.0, f64::max)
+    }
+
+    /// Call `f` with the index and the two values of every channel of both images.
+    ///
+    /// Both images must have the same depth, which `validate_image_compatibility`
+    /// guarantees by checking the color type.
+    pub(crate) fn for_each_pair(&self, other: &Samples, mut f: impl FnMut(usize, f64, f64)) {
+        match (self, other) {
+            (Samples::U8(s1), Samples::U8(s2)) => {
+                for (i, (&a, &b)) in s1.iter().zip(s2.iter()).enumerate() {
+                    f(i, f64::from(a), f64::from(b));
+                }
+            }
+            (Samples::U16(s1), Samples::U16(s2)) => {
+                for (i, (&a, &b)) in s1.iter().zip(s2.iter()).enumerate() {
+                    f(i, f64::from(a), f64::from(b));
+                }
+            }
+            (Samples::F32(s1), Samples::F32(s2)) => {
+                for (i, (&a, &b)) in s1.iter().zip(s2.iter()).enumerate() {
+                    f(i, f64::from(a), f64::from(b));
+                }
+            }
+            _ => panic!("images must have the same bit depth"),
+        }
+    }
+}
+
+/// The value of a fully saturated channel for the two images: 255 or 65535 for
+/// integer images. Floating-point images are nominally in [0, 1], but HDR images
+/// can be brighter, in which case the brightest channel of either image is used
+/// so that normalised differences stay within [0, 1].
+pub fn max_channel_value(image1: &DynamicImage, image2: &DynamicImage) -> f64 {
+    match Samples::of(image1) {
+        Samples::U8(_) => f64::from(u8::MAX),
+        Samples::U16(_) => f64::from(u16::MAX),
+        s @ Samples::F32(_) => s.max().max(Samples::of(image2).max()).max(1.0),
+    }
+}
+
+/// Like `max_channel_value`, but in the [0, 1] space of `to_*32f()` conversions
+pub(crate) fn float_max(image1: &DynamicImage, image2: &DynamicImage) -> f64 {
+    match Samples::of(image1) {
+        Samples::F32(_) => max_channel_value(image1, image2),
+        _ => 1.0,
+    }
+}
+
+/// Whether the image stores 8 bits per channel
+pub(crate) fn is_8bit(image: &DynamicImage) -> bool {
+    matches!(Samples::of(image), Samples::U8(_))
+}
+
+/// Build an 8-bit image from raw channel values, picking the color type from the
+/// number of channels
+pub(crate) fn from_raw8(width: u32, height: u32, channels: u8, raw: Vec<u8>) -> DynamicImage {
+    // unwrap() is safe: callers produce exactly width * height * channels values
+    match channels {
+        1 => DynamicImage::ImageLuma8(ImageBuffer::from_raw(width, height, raw).unwrap()),
+        2 => DynamicImage::ImageLumaA8(ImageBuffer::from_raw(width, height, raw).unwrap()),
+        3 => DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, height, raw).unwrap()),
+        _ => DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, raw).unwrap()),
+    }
+}
+
+/// How a diff image of 16-bit or floating-point inputs is converted for writing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Quantization {
+    /// Map the native range linearly onto 8 bits; floating-point values are
+    /// clamped to [0, 1]
+    #[default]
+    Scale,
+    /// Map the largest difference present onto the brightest 8-bit value, making
+    /// small differences visible. This also applies to 8-bit inputs.
+    Stretch,
+    /// Keep the native depth; the output format must be able to store it
+    Native,
+}
+
+impl Quantization {
+    /// Convert a diff image for writing
+    pub fn apply(self, diff: &DynamicImage) -> DynamicImage {
+        match self {
+            Quantization::Native => diff.clone(),
+            Quantization::Scale if is_8bit(diff) => diff.clone(),
+            Quantization::Scale => match diff {
+                DynamicImage::ImageLuma16(_) => DynamicImage::ImageLuma8(diff.to_luma8()),
+                DynamicImage::ImageLumaA16(_) => DynamicImage::ImageLumaA8(diff.to_luma_alpha8()),
+                DynamicImage::ImageRgb16(_) | DynamicImage::ImageRgb32F(_) => {
+                    DynamicImage::ImageRgb8(diff.to_rgb8())
+                }
+                _ => DynamicImage::ImageRgba8(diff.to_rgba8()),
+            },
+            Quantization::Stretch => {
+                let samples = Samples::of(diff);
+                let max = samples.max();
+                let factor = if max > 0.0 { 255.0 / max } else { 0.0 };
+                let raw = (0..samples.len())
+                    .map(|i| (samples.get(i) * factor).round() as u8)
+                    .collect();
+                let (w, h) = diff.dimensions();
+                from_raw8(w, h, diff.color().channel_count(), raw)
+            }
+        }
+    }
+}
+
+impl FromStr for Quantization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Quantization, String> {
+        match s {
+            "scale" => Ok(Quantization::Scale),
+            "stretch" => Ok(Quantization::Stretch),
+            "native" => Ok(Quantization::Native),
+            _ => Err(format!("unknown quantization \"{}\"", s)),
+        }
+    }
+}
+
+#[test]
+fn test_stretch() {
+    let diff = DynamicImage::ImageLuma16(ImageBuffer::from_raw(2, 1, vec![0u16, 1000]).unwrap());
+    let stretched = Quantization::Stretch.apply(&diff);
+    assert_eq!(stretched.as_bytes(), &[0, 255]);
+    let scaled = Quantization::Scale.apply(&diff);
+    assert_eq!(scaled.as_bytes(), &[0, 4]);
+}
@@ -0,0 +1,109 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use image::{ColorType, ImageError};
+
+/// Everything that can go wrong while diffing two images
+#[derive(Debug)]
+pub enum DiffError {
+    /// The given path does not exist
+    MissingFile(String),
+    /// The file exists but could not be decoded as an image
+    Decode { path: String, source: ImageError },
+    /// The two images have different (width, height)
+    DimensionMismatch {
+        image1: (u32, u32),
+        image2: (u32, u32),
+    },
+    /// The two images have different color modes
+    ColorMismatch {
+        image1: ColorType,
+        image2: ColorType,
+    },
+    /// The diff image could not be written
+    Save { path: String, source: ImageError },
+    /// The mask image has different (width, height) than the images compared
+    MaskSize { mask: (u32, u32), image: (u32, u32) },
+    /// The region file could not be read or parsed
+    RegionFile { path: String, message: String },
+    /// A region does not overlap the images at all
+    RegionOutside { name: String, image: (u32, u32) },
+    /// A directory of images could not be read
+    ListDir { path: String, source: io::Error },
+    /// A directory to write outputs to could not be created
+    CreateDir { path: String, source: io::Error },
+}
+
+impl fmt::Display for DiffError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiffError::MissingFile(path) => write!(f, "Path \"{}\" does not exist", path),
+            DiffError::Decode { path, source } => {
+                write!(f, "could not decode \"{}\": {}", path, source)
+            }
+            DiffError::DimensionMismatch { image1, image2 } => write!(
+                f,
+                "images must have the same dimensions ({}x{} vs {}x{})",
+                image1.0, image1.1, image2.0, image2.1
+            ),
+            DiffError::ColorMismatch { image1, image2 } => write!(
+                f,
+                "images must have the same color mode ({:?} vs {:?})",
+                image1, image2
+            ),
+            DiffError::Save { path, source } => {
+                write!(f, "could not write \"{}\": {}", path, source)
+            }
+            DiffError::MaskSize { mask, image } => write!(
+                f,
+                "mask must have the same dimensions as the images ({}x{} vs {}x{})",
+                mask.0, mask.1, image.0, image.1
+            ),
+            DiffError::RegionFile { path, message } => {
+                write!(f, "could not read regions from \"{}\": {}", path, message)
+            }
+            DiffError::RegionOutside { name, image } => write!(
+                f,
+                "region \"{}\" lies outside the images ({}x{})",
+                name, image.0, image.1
+            ),
+            DiffError::ListDir { path, source } => {
+                write!(f, "could not list directory \"{}\": {}", path, source)
+            }
+            DiffError::CreateDir { path, source } => {
+                write!(f, "could not create directory \"{}\": {}", path, source)
+            }
+        }
+    }
+}
+
+impl DiffError {
+    /// A short, stable name for the kind of error, for other programs to match on
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DiffError::MissingFile(_) => "missing_file",
+            DiffError::Decode { .. } => "decode",
+            DiffError::DimensionMismatch { .. } => "dimension_mismatch",
+            DiffError::ColorMismatch { .. } => "color_mismatch",
+            DiffError::Save { .. } => "save",
+            DiffError::MaskSize { .. } => "mask_size",
+            DiffError::RegionFile { .. } => "region_file",
+            DiffError::RegionOutside { .. } => "region_outside",
+            DiffError::ListDir { .. } => "list_dir",
+            DiffError::CreateDir { .. } => "create_dir",
+        }
+    }
+}
+
+impl Error for DiffError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DiffError::Decode { source, .. } => Some(source),
+            DiffError::Save { source, .. } => Some(source),
+            DiffError::ListDir { source, .. } => Some(source),
+            DiffError::CreateDir { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//! A self-contained HTML page for reviewing a comparison in the browser, with the
+//! images embedded as data URIs so the page is a single file.
+
+use std::fmt::Write;
+use std::io::Cursor;
+
+use image::{DynamicImage, GenericImageView, ImageError, ImageFormat};
+
+use crate::{ChangeBox, Report, Verdict};
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode bytes as standard base64 with padding
+fn base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from(b[0]) << 16 | u32::from(b[1]) << 8 | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3f] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// The image as a PNG data URI. Browsers only show 8-bit images reliably, so
+/// deeper images are converted.
+fn data_uri(image: &DynamicImage) -> Result<String, ImageError> {
+    let image = match image {
+        DynamicImage::ImageLuma8(_) | DynamicImage::ImageRgb8(_) => image.clone(),
+        _ if image.color().has_alpha() => DynamicImage::ImageRgba8(image.to_rgba8()),
+        _ => DynamicImage::ImageRgb8(image.to_rgb8()),
+    };
+    let mut png = Cursor::new(Vec::new());
+    image.write_to(&mut png, ImageFormat::Png)?;
+    Ok(format!("data:image/png;base64,{}", base64(png.get_ref())))
+}
+
+/// Escape text for use in HTML content and attribute values
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin-bottom: 1.5em; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; }
+.pass { color: #080; } .fail { color: #c00; }
+.images { display: flex; flex-wrap: wrap; gap: 1em; }
+.images figure { margin: 0; }
+img { max-width: 100%; image-rendering: pixelated; background: repeating-conic-gradient(#ddd 0 25%, #fff 0 50%) 0 0 / 16px 16px; }
+.swipe { display: inline-block; max-width: 100%; }
+.stack { position: relative; display: inline-block; max-width: 100%; }
+.stack img { display: block; width: 100%; }
+.stack .top { position: absolute; top: 0; left: 0; clip-path: inset(0 50% 0 0); }
+.box { position: absolute; border: 2px solid red; box-sizing: border-box; }
+input[type=range] { width: 100%; }
+";
+
+const SCRIPT: &str = "
+function swipe(percent) {
+    document.getElementById('swipe-top').style.clipPath = 'inset(0 ' + (100 - percent) + '% 0 0)';
+}
+var showingSecond = false;
+function flip() {
+    showingSecond = !showingSecond;
+    var source = document.getElementById(showingSecond ? 'image2' : 'image1');
+    document.getElementById('blink-image').src = source.src;
+    document.getElementById('blink-label').textContent = source.alt;
+}
+var blinkTimer = null;
+function autoBlink(on) {
+    clearInterval(blinkTimer);
+    blinkTimer = on ? setInterval(flip, 500) : null;
+}
+";
+
+/// Render a page with the two images, their diff, a swipe view, a blink toggle,
+/// the measurements and the bounding boxes of the changed areas. The images must
+/// have the dimensions the report was compared at; the diff may be taller when a
+/// legend was added below it.
+///
+/// The diff is shown without alpha, since the alpha of an absolute difference is
+/// zero wherever alpha did not change.
+pub fn html_report(
+    report: &Report,
+    (image1, image2): (&DynamicImage, &DynamicImage),
+    diff: &DynamicImage,
+    boxes: &[ChangeBox],
+) -> Result<String, ImageError> {
+    let (width, height) = image1.dimensions();
+    let opaque_diff = DynamicImage::ImageRgb8(diff.to_rgb8());
+    let (uri1, uri2, diff_uri) = (
+        data_uri(image1)?,
+        data_uri(image2)?,
+        data_uri(&opaque_diff)?,
+    );
+    let (name1, name2) = (escape(&report.image1.path), escape(&report.image2.path));
+    let verdict = |verdict: Option<Verdict>| match verdict {
+        Some(Verdict::Pass) => "<span class=\"pass\">pass</span>",
+        Some(Verdict::Fail) => "<span class=\"fail\">fail</span>",
+        None => "",
+    };
+
+    // Writing to a String cannot fail, so the results of write! are ignored
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>diffimg: {} vs {}</title>\n<style>{}</style>\n<script>{}</script>\n\
+         </head>\n<body>\n<h1>{} vs {}: {}</h1>\n",
+        name1,
+        name2,
+        STYLE,
+        SCRIPT,
+        name1,
+        name2,
+        verdict(report.verdict)
+    );
+
+    html.push_str("<table>\n<tr><th>Image</th><th>Size</th><th>Color</th></tr>\n");
+    for info in &[&report.image1, &report.image2] {
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td>{}x{}</td><td>{}</td></tr>",
+            escape(&info.path),
+            info.width.unwrap_or(0),
+            info.height.unwrap_or(0),
+            info.color.unwrap_or("")
+        );
+    }
+    html.push_str("</table>\n");
+
+    let _ = writeln!(
+        html,
+        "<h2>Measurements</h2>\n<table>\n<tr><th>Area</th><th>{}</th><th>Channels</th>\
+         <th>Differing pixels</th><th>Verdict</th></tr>",
+        report.metric
+    );
+    for m in &report.measurements {
+        let area = match m.region {
+            Some(ref region) => format!("{} ({})", escape(&region.name), region.rect),
+            None => format!("whole image ({}x{})", width, height),
+        };
+        let channels = m
+            .channels
+            .as_ref()
+            .map(|c| {
+                c.iter()
+                    .map(|v| v.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .unwrap_or_default();
+        let pixels = m
+            .pixels
+            .map(|p| format!("{} of {} ({:.3}%)", p.differing, p.total, p.percentage()))
+            .unwrap_or_default();
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            area,
+            m.value,
+            channels,
+            pixels,
+            verdict(Some(m.verdict))
+        );
+    }
+    html.push_str("</table>\n");
+
+    let _ = write!(
+        html,
+        "<h2>Images</h2>\n<div class=\"images\">\n\
+         <figure><img id=\"image1\" src=\"{}\" alt=\"{}\"><figcaption>{}</figcaption></figure>\n\
+         <figure><img id=\"image2\" src=\"{}\" alt=\"{}\"><figcaption>{}</figcaption></figure>\n\
+         <figure><div class=\"stack\"><img src=\"{}\" alt=\"diff\">",
+        uri1, name1, name1, uri2, name2, name2, diff_uri
+    );
+    // The boxes are placed relative to the whole diff, including any legend
+    let (diff_width, diff_height) = diff.dimensions();
+    for b in boxes {
+        let percent = |v: u32, of: u32| 100.0 * f64::from(v) / f64::from(of);
+        let _ = write!(
+            html,
+            "<div class=\"box\" style=\"left: {}%; top: {}%; width: {}%; height: {}%\"></div>",
+            percent(b.rect.x, diff_width),
+            percent(b.rect.y, diff_height),
+            percent(b.rect.width, diff_width),
+            percent(b.rect.height, diff_height)
+        );
+    }
+    html.push_str("</div><figcaption>diff</figcaption></figure>\n</div>\n");
+
+    let _ = write!(
+        html,
+        "<h2>Swipe</h2>\n<p>Left: {}, right: {}</p>\n\
+         <div class=\"swipe\"><div class=\"stack\"><img src=\"{}\" alt=\"{}\">\
+         <img id=\"swipe-top\" class=\"top\" src=\"{}\" alt=\"{}\"></div>\n\
+         <input type=\"range\" min=\"0\" max=\"100\" value=\"50\" oninput=\"swipe(this.value)\"></div>\n",
+        name1, name2, uri2, name2, uri1, name1
+    );
+
+    let _ = write!(
+        html,
+        "<h2>Blink</h2>\n<p><button onclick=\"flip()\">Flip</button> \
+         <label><input type=\"checkbox\" onchange=\"autoBlink(this.checked)\"> Flip automatically</label> \
+         Showing: <span id=\"blink-label\">{}</span></p>\n\
+         <img id=\"blink-image\" src=\"{}\" alt=\"blink\">\n",
+        name1, uri1
+    );
+
+    let _ = writeln!(
+        html,
+        "<h2>Changed areas</h2>\n<p>{} changed areas</p>",
+        boxes.len()
+    );
+    if !boxes.is_empty() {
+        html.push_str("<table>\n<tr><th>Area</th><th>Pixels</th><th>Mean intensity</th></tr>\n");
+        for b in boxes {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{:.4}</td></tr>",
+                b.rect, b.pixels, b.mean_intensity
+            );
+        }
+        html.push_str("</table>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    Ok(html)
+}
+
+#[test]
+fn test_base64() {
+    assert_eq!(base64(b""), "");
+    assert_eq!(base64(b"M"), "TQ==");
+    assert_eq!(base64(b"Ma"), "TWE=");
+    assert_eq!(base64(b"Man"), "TWFu");
+    assert_eq!(
+        escape("<a href=\"x\">&</a>"),
+        "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;"
+    );
+}
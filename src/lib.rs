@@ -1,16 +1,303 @@
 extern crate clap;
 extern crate image;
+extern crate png;
+#[cfg(feature = "parallel")]
+extern crate rayon;
+extern crate serde;
+extern crate serde_json;
 
+mod alpha;
+mod animate;
+mod antialias;
+mod batch;
+mod boxes;
+mod composite;
+mod deltae;
+mod depth;
+mod error;
+mod html;
+mod mask;
+mod mse;
+mod normalize;
+mod par;
+mod region;
+mod report;
+mod ssim;
+mod stats;
+mod style;
+mod text;
+
+use std::fmt;
+use std::fs;
 use std::path::Path;
+use std::str::FromStr;
 
 use clap::ArgMatches;
-use image::{DynamicImage, GenericImage, GenericImageView};
+use image::imageops::FilterType;
+use image::{ColorType, DynamicImage, GenericImageView, ImageBuffer, ImageError, Pixel, Rgba};
+use serde::{Serialize, Serializer};
+
+use depth::Samples;
+
+pub use alpha::{AlphaMode, DEFAULT_ALPHA_BACKGROUND};
+pub use animate::{
+    blink_frames, onion_skin, save_animation, DEFAULT_FRAME_DELAY_MS, DEFAULT_ONION_OPACITY,
+};
+pub use antialias::{find_antialiased, DEFAULT_ANTIALIASED_COLOR};
+pub use batch::{compare_dirs, default_jobs, run_batch, BatchEntry, BatchReport, FileStatus};
+pub use boxes::{draw_boxes, find_changed_boxes, ChangeBox, BOX_COLOR, DEFAULT_MERGE_DISTANCE};
+pub use composite::{composite, Composite, Layout, Panel, DEFAULT_GUTTER, MAX_GUTTER};
+pub use deltae::{
+    delta_e2000, delta_e76, delta_e94, delta_e_map, srgb_float_to_lab, srgb_to_lab, DeltaEFormula,
+    DeltaEMap, Lab,
+};
+pub use depth::{max_channel_value, Quantization};
+pub use error::DiffError;
+pub use html::html_report;
+pub use mask::{Mask, Rect, DEFAULT_MASK_COLOR};
+pub use mse::{calculate_error_metrics, calculate_masked_error_metrics, ErrorMetrics};
+pub use normalize::{
+    normalize_color, parse_color, parse_color_type, parse_filter, SizeMismatch,
+    DEFAULT_NORMALIZED_COLOR,
+};
+pub use region::{crop, load_regions, parse_regions, Region};
+pub use report::{ErrorInfo, ImageInfo, Measurement, OutputFormat, Outputs, Report};
+pub use ssim::{calculate_ms_ssim, calculate_ssim, ssim_map, SsimMap};
+pub use stats::{calculate_channel_stats, ChannelStats, HISTOGRAM_BINS};
+pub use style::{
+    difference_magnitude, heatmap, overlay, overlay_image, with_legend, Colormap, DiffStyle,
+    DEFAULT_HIGHLIGHT_COLOR,
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
 pub struct Config<'a> {
     pub image1: &'a str,
     pub image2: &'a str,
+    /// Convert both images to this color type before comparing them
+    pub normalize_color: Option<ColorType>,
+    /// How to handle images with different dimensions
+    pub size_mismatch: SizeMismatch,
+    /// How the alpha channel takes part in the comparison
+    pub alpha: AlphaMode,
+    /// Rectangles to leave out of the comparison
+    pub ignore: Vec<Rect>,
+    /// An image whose black pixels are left out of the comparison
+    pub mask: Option<&'a str>,
+    /// Color of ignored pixels on the diff image; defaults to `DEFAULT_MASK_COLOR`
+    pub mask_color: Option<Rgba<u8>>,
+    /// Treat differing pixels that look anti-aliased as equal, see `find_antialiased`
+    pub ignore_antialiasing: bool,
+    /// Color of anti-aliased pixels on the diff image; defaults to
+    /// `DEFAULT_ANTIALIASED_COLOR`
+    pub antialiased_color: Option<Rgba<u8>>,
+    /// Compare only these regions, each reported separately
+    pub regions: Vec<Region>,
+    /// A file with more regions, see `parse_regions`
+    pub region_file: Option<&'a str>,
+    /// Write the diff image to this file; when comparing directories, the
+    /// directory to write the diff images of changed files to
     pub filename: Option<&'a str>,
+    /// What the diff image looks like
+    pub style: DiffStyle,
+    /// Color of changed pixels on an overlay; defaults to `DEFAULT_HIGHLIGHT_COLOR`
+    pub highlight_color: Option<Rgba<u8>>,
+    /// Colors of a heatmap
+    pub colormap: Colormap,
+    /// The value shown at the low end of a heatmap; defaults to 0
+    pub heatmap_min: Option<f64>,
+    /// The value shown at the high end of a heatmap; defaults to 1, or 100 for ΔE
+    pub heatmap_max: Option<f64>,
+    /// Add a legend below a heatmap
+    pub legend: bool,
+    /// Write the inputs and the diff laid out in one image instead of the diff
+    pub composite: Option<Composite>,
+    /// Write an animation flipping between the images to this GIF or PNG file
+    pub blink: Option<&'a str>,
+    /// Add the diff as a third frame of the blink animation
+    pub blink_diff: bool,
+    /// How long each frame of the blink animation is shown, in milliseconds.
+    /// Defaults to `DEFAULT_FRAME_DELAY_MS`.
+    pub frame_delay: Option<u16>,
+    /// Write the second image blended over the first to this file
+    pub onion_skin: Option<&'a str>,
+    /// Opacity of the second image in the onion skin, from 0 to 1.
+    /// Defaults to `DEFAULT_ONION_OPACITY`.
+    pub onion_opacity: Option<f64>,
+    /// Color mode of the diff image; defaults to that of the inputs
+    pub diff_color: Option<DiffColor>,
+    /// How a diff image of 16-bit or floating-point inputs is written
+    pub quantization: Quantization,
+    /// Which metric to compute and print
+    pub metric: Metric,
+    /// Fail if the metric is worse than this value
+    pub threshold: Option<f64>,
+    /// Fail if more than this many pixels differ
+    pub max_pixels: Option<u64>,
+    /// A pixel only counts as different if a channel differs by more than this.
+    /// When set, the number of differing pixels is reported.
+    pub pixel_tolerance: Option<u8>,
+    /// With a ΔE metric, pixels at or below this ΔE count as equal.
+    /// Defaults to `DEFAULT_DELTA_E_TOLERANCE`.
+    pub delta_e_tolerance: Option<f64>,
+    /// Print the mean, maximum, standard deviation and histogram of the
+    /// differences of each channel
+    pub stats: bool,
+    /// Print the bounding boxes of the changed areas
+    pub boxes: bool,
+    /// Merge changed areas at most this many pixels apart.
+    /// Defaults to `DEFAULT_MERGE_DISTANCE`.
+    pub merge_distance: Option<u32>,
+    /// Draw the bounding boxes over the first image and write it to this file
+    pub boxes_image: Option<&'a str>,
+    /// Write an HTML page for reviewing the comparison to this file
+    pub html_report: Option<&'a str>,
+    /// When comparing directories, how many pairs to compare at once.
+    /// Defaults to `default_jobs()`.
+    pub jobs: Option<usize>,
+    /// How the results are printed
+    pub format: OutputFormat,
+}
+
+/// Roughly the smallest ΔE a human observer can notice
+pub const DEFAULT_DELTA_E_TOLERANCE: f64 = 2.3;
+
+/// The measure used to compare two images
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Metric {
+    /// Mean absolute difference of every channel value, see `calculate_diff_ratio`
+    #[default]
+    Ratio,
+    /// Structural similarity, see `calculate_ssim`
+    Ssim,
+    /// Multi-scale structural similarity, see `calculate_ms_ssim`
+    MsSsim,
+    /// Mean squared error, see `calculate_error_metrics`
+    Mse,
+    /// Root mean squared error, see `calculate_error_metrics`
+    Rmse,
+    /// Peak signal-to-noise ratio in dB, see `calculate_error_metrics`
+    Psnr,
+    /// Mean perceptual color difference, see `delta_e_map`
+    DeltaE(DeltaEFormula),
+}
+
+impl Metric {
+    /// Whether a higher value of this metric means the images are more alike
+    pub fn higher_is_better(self) -> bool {
+        match self {
+            Metric::Ratio | Metric::Mse | Metric::Rmse | Metric::DeltaE(_) => false,
+            Metric::Ssim | Metric::MsSsim | Metric::Psnr => true,
+        }
+    }
+
+    /// Whether `value` is within tolerance of `threshold` for this metric
+    pub fn within(self, value: f64, threshold: f64) -> bool {
+        if self.higher_is_better() {
+            value >= threshold
+        } else {
+            value <= threshold
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Metric::Ratio => "ratio",
+            Metric::Ssim => "ssim",
+            Metric::MsSsim => "msssim",
+            Metric::Mse => "mse",
+            Metric::Rmse => "rmse",
+            Metric::Psnr => "psnr",
+            Metric::DeltaE(DeltaEFormula::Cie76) => "cie76",
+            Metric::DeltaE(DeltaEFormula::Cie94) => "cie94",
+            Metric::DeltaE(DeltaEFormula::Ciede2000) => "ciede2000",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Serialize for Metric {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl FromStr for Metric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Metric, String> {
+        match s {
+            "ratio" => Ok(Metric::Ratio),
+            "ssim" => Ok(Metric::Ssim),
+            "msssim" => Ok(Metric::MsSsim),
+            "mse" => Ok(Metric::Mse),
+            "rmse" => Ok(Metric::Rmse),
+            "psnr" => Ok(Metric::Psnr),
+            "cie76" => Ok(Metric::DeltaE(DeltaEFormula::Cie76)),
+            "cie94" => Ok(Metric::DeltaE(DeltaEFormula::Cie94)),
+            "ciede2000" => Ok(Metric::DeltaE(DeltaEFormula::Ciede2000)),
+            _ => Err(format!("unknown metric \"{}\"", s)),
+        }
+    }
+}
+
+/// Color mode to convert the diff image to before writing it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffColor {
+    Gray,
+    GrayAlpha,
+    Rgb,
+    Rgba,
+}
+
+impl DiffColor {
+    /// Convert the image to this color mode, keeping 16-bit and floating-point
+    /// images at a high depth (16-bit for gray, since there is no f32 gray)
+    pub fn convert(self, image: &DynamicImage) -> DynamicImage {
+        match Samples::of(image) {
+            Samples::U8(_) => match self {
+                DiffColor::Gray => DynamicImage::ImageLuma8(image.to_luma8()),
+                DiffColor::GrayAlpha => DynamicImage::ImageLumaA8(image.to_luma_alpha8()),
+                DiffColor::Rgb => DynamicImage::ImageRgb8(image.to_rgb8()),
+                DiffColor::Rgba => DynamicImage::ImageRgba8(image.to_rgba8()),
+            },
+            Samples::U16(_) => match self {
+                DiffColor::Gray => DynamicImage::ImageLuma16(image.to_luma16()),
+                DiffColor::GrayAlpha => DynamicImage::ImageLumaA16(image.to_luma_alpha16()),
+                DiffColor::Rgb => DynamicImage::ImageRgb16(image.to_rgb16()),
+                DiffColor::Rgba => DynamicImage::ImageRgba16(image.to_rgba16()),
+            },
+            Samples::F32(_) => match self {
+                DiffColor::Gray => DynamicImage::ImageLuma16(image.to_luma16()),
+                DiffColor::GrayAlpha => DynamicImage::ImageLumaA16(image.to_luma_alpha16()),
+                DiffColor::Rgb => DynamicImage::ImageRgb32F(image.to_rgb32f()),
+                DiffColor::Rgba => DynamicImage::ImageRgba32F(image.to_rgba32f()),
+            },
+        }
+    }
+}
+
+impl FromStr for DiffColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DiffColor, String> {
+        match s {
+            "gray" => Ok(DiffColor::Gray),
+            "graya" => Ok(DiffColor::GrayAlpha),
+            "rgb" => Ok(DiffColor::Rgb),
+            "rgba" => Ok(DiffColor::Rgba),
+            _ => Err(format!("unknown color mode \"{}\"", s)),
+        }
+    }
+}
+
+/// Whether the images were found to be within the configured tolerance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    Pass,
+    Fail,
 }
 
 impl<'a> Config<'a> {
@@ -18,22 +305,164 @@ impl<'a> Config<'a> {
         // unwrap() should be safe here because clap does argument validation
         let image1 = matches.value_of("image1").unwrap();
         let image2 = matches.value_of("image2").unwrap();
+        let normalize_color = match matches.value_of("normalize_to") {
+            Some(v) => Some(parse_color_type(v).unwrap()),
+            None if matches.is_present("normalize_color") => Some(DEFAULT_NORMALIZED_COLOR),
+            None => None,
+        };
+        let size_mismatch = match matches.value_of("size_mismatch") {
+            Some("crop") => SizeMismatch::Crop,
+            Some("pad") => SizeMismatch::Pad(
+                matches
+                    .value_of("pad_color")
+                    .map(|v| parse_color(v).unwrap())
+                    .unwrap_or(Rgba([0, 0, 0, 0])),
+            ),
+            Some("resize") => SizeMismatch::Resize(
+                matches
+                    .value_of("resize_filter")
+                    .map(|v| parse_filter(v).unwrap())
+                    .unwrap_or(FilterType::Lanczos3),
+            ),
+            _ => SizeMismatch::Error,
+        };
+        let alpha = match matches.value_of("alpha") {
+            Some("composite") => AlphaMode::Composite(
+                matches
+                    .value_of("alpha_background")
+                    .map(|v| parse_color(v).unwrap())
+                    .unwrap_or(DEFAULT_ALPHA_BACKGROUND),
+            ),
+            Some("transparent") => AlphaMode::TransparentEqual,
+            Some("ignore") => AlphaMode::Ignore,
+            _ => AlphaMode::Raw,
+        };
+        let ignore = matches
+            .values_of("ignore")
+            .map(|values| values.map(|v| v.parse().unwrap()).collect())
+            .unwrap_or_default();
+        let mask = matches.value_of("mask");
+        let mask_color = matches
+            .value_of("mask_color")
+            .map(|v| parse_color(v).unwrap());
+        let ignore_antialiasing = matches.is_present("ignore_antialiasing");
+        let antialiased_color = matches
+            .value_of("antialiased_color")
+            .map(|v| parse_color(v).unwrap());
+        let regions = matches
+            .values_of("region")
+            .map(|values| {
+                values
+                    .map(|v| Region::unnamed(v.parse().unwrap()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let region_file = matches.value_of("region_file");
         let filename = matches.value_of("filename");
+        let style = matches
+            .value_of("style")
+            .map(|v| v.parse().unwrap())
+            .unwrap_or_default();
+        let highlight_color = matches
+            .value_of("highlight_color")
+            .map(|v| parse_color(v).unwrap());
+        let colormap = matches
+            .value_of("colormap")
+            .map(|v| v.parse().unwrap())
+            .unwrap_or_default();
+        let heatmap_min = matches.value_of("heatmap_min").map(|v| v.parse().unwrap());
+        let heatmap_max = matches.value_of("heatmap_max").map(|v| v.parse().unwrap());
+        let legend = matches.is_present("legend");
+        let composite = matches.value_of("composite").map(|v| Composite {
+            layout: v.parse().unwrap(),
+            include_diff: matches.value_of("composite_panels") != Some("2"),
+            gutter: matches
+                .value_of("gutter")
+                .map(|v| v.parse().unwrap())
+                .unwrap_or(DEFAULT_GUTTER),
+        });
+        let blink = matches.value_of("blink");
+        let blink_diff = matches.is_present("blink_diff");
+        let frame_delay = matches.value_of("frame_delay").map(|v| v.parse().unwrap());
+        let onion_skin = matches.value_of("onion_skin");
+        let onion_opacity = matches.value_of("opacity").map(|v| v.parse().unwrap());
+        let diff_color = matches.value_of("diff_color").map(|v| v.parse().unwrap());
+        let quantization = matches
+            .value_of("quantization")
+            .map(|v| v.parse().unwrap())
+            .unwrap_or_default();
+        let metric = matches
+            .value_of("metric")
+            .map(|v| v.parse().unwrap())
+            .unwrap_or_default();
+        let threshold = matches.value_of("threshold").map(|v| v.parse().unwrap());
+        let max_pixels = matches.value_of("max_pixels").map(|v| v.parse().unwrap());
+        let pixel_tolerance = matches
+            .value_of("pixel_tolerance")
+            .map(|v| v.parse().unwrap());
+        let delta_e_tolerance = matches
+            .value_of("delta_e_tolerance")
+            .map(|v| v.parse().unwrap());
+        let stats = matches.is_present("stats");
+        let boxes = matches.is_present("boxes");
+        let merge_distance = matches
+            .value_of("merge_distance")
+            .map(|v| v.parse().unwrap());
+        let boxes_image = matches.value_of("boxes_image");
+        let html_report = matches.value_of("html_report");
+        let jobs = matches.value_of("jobs").map(|v| v.parse().unwrap());
+        let format = matches
+            .value_of("format")
+            .map(|v| v.parse().unwrap())
+            .unwrap_or_default();
 
         Config {
             image1,
             image2,
+            normalize_color,
+            size_mismatch,
+            alpha,
+            ignore,
+            mask,
+            mask_color,
+            ignore_antialiasing,
+            antialiased_color,
+            regions,
+            region_file,
             filename,
+            style,
+            highlight_color,
+            colormap,
+            heatmap_min,
+            heatmap_max,
+            legend,
+            composite,
+            blink,
+            blink_diff,
+            frame_delay,
+            onion_skin,
+            onion_opacity,
+            diff_color,
+            quantization,
+            metric,
+            threshold,
+            max_pixels,
+            pixel_tolerance,
+            delta_e_tolerance,
+            stats,
+            boxes,
+            merge_distance,
+            boxes_image,
+            html_report,
+            jobs,
+            format,
         }
     }
 }
 
 /// abs(x - y) for u8
 fn abs_diff(x: u8, y: u8) -> u8 {
-    if x > y {
-        return x - y;
-    }
-    return y - x;
+    x.abs_diff(y)
 }
 
 #[test]
@@ -45,101 +474,809 @@ fn test_abs_diff() {
 }
 
 /// Return the image from the file path, or throw an error
-fn safe_load_image(raw_path: &str) -> Result<DynamicImage, String> {
-    let path = &Path::new(raw_path);
-    if !Path::exists(path) {
-        return Err(format!("Path \"{}\" does not exist", raw_path));
+fn safe_load_image(raw_path: &str) -> Result<DynamicImage, DiffError> {
+    let path = Path::new(raw_path);
+    if !path.exists() {
+        return Err(DiffError::MissingFile(raw_path.to_string()));
     }
 
-    match image::open(path) {
-        Ok(image) => Ok(image),
-        Err(msg) => Err(format!("{:?}", msg)),
-    }
+    image::open(path).map_err(|source| DiffError::Decode {
+        path: raw_path.to_string(),
+        source,
+    })
 }
 
 /// Check if two images are the same size and color mode
 fn validate_image_compatibility(
     image1: &DynamicImage,
     image2: &DynamicImage,
-) -> Result<(), String> {
+) -> Result<(), DiffError> {
     if image1.dimensions() != image2.dimensions() {
-        return Err("images must have the same dimensions".to_string());
+        return Err(DiffError::DimensionMismatch {
+            image1: image1.dimensions(),
+            image2: image2.dimensions(),
+        });
     }
     if image1.color() != image2.color() {
-        return Err("images must have the same color mode".to_string());
+        return Err(DiffError::ColorMismatch {
+            image1: image1.color(),
+            image2: image2.color(),
+        });
     }
 
     Ok(())
 }
 
 /// Return a difference ratio between 0 and 1 for the two images
-pub fn calculate_diff_ratio(image1: DynamicImage, image2: DynamicImage) -> f64 {
-    // All color types wrap an 8-bit value for each channel
-    let max_val = u64::pow(2, 8) - 1;
-    let mut diffsum: u64 = 0;
-    for (&p1, &p2) in image1.raw_pixels().iter().zip(image2.raw_pixels().iter()) {
-        diffsum += u64::from(abs_diff(p1, p2));
+pub fn calculate_diff_ratio(image1: &DynamicImage, image2: &DynamicImage) -> f64 {
+    match (Samples::of(image1), Samples::of(image2)) {
+        (Samples::U8(raw1), Samples::U8(raw2)) => {
+            // Sum 8-bit images exactly in integers
+            let max_val = u64::from(u8::MAX);
+            let rows = par::map_ranges(raw1.len(), par::row_len(image1), |range| {
+                raw1[range.clone()]
+                    .iter()
+                    .zip(raw2[range].iter())
+                    .map(|(&p1, &p2)| u64::from(abs_diff(p1, p2)))
+                    .sum::<u64>()
+            });
+            let diffsum: u64 = rows.iter().sum();
+            let total_possible = max_val * raw1.len() as u64;
+
+            diffsum as f64 / total_possible as f64
+        }
+        (samples1, samples2) => {
+            let max_val = max_channel_value(image1, image2);
+            // Rows are summed first, then the row sums in order, so that
+            // floating-point rounding doesn't depend on the number of threads
+            let rows = par::map_ranges(samples1.len(), par::row_len(image1), |range| {
+                let mut sum = 0.0;
+                samples1
+                    .slice(range.clone())
+                    .for_each_pair(&samples2.slice(range), |_, p1, p2| sum += (p1 - p2).abs());
+                sum
+            });
+            let diffsum: f64 = rows.iter().sum();
+
+            diffsum / (max_val * samples1.len() as f64)
+        }
     }
-    let total_possible = max_val * image1.raw_pixels().len() as u64;
-    let ratio = diffsum as f64 / total_possible as f64;
+}
+
+/// Like `calculate_diff_ratio`, but only over the pixels the mask does not ignore.
+/// Returns 0 when every pixel is ignored.
+pub fn calculate_masked_diff_ratio(
+    image1: &DynamicImage,
+    image2: &DynamicImage,
+    mask: &Mask,
+) -> f64 {
+    let channels = u64::from(image1.color().channel_count());
+    let included = mask.included_count() * channels;
+    if included == 0 {
+        return 0.0;
+    }
+
+    let (samples1, samples2) = (Samples::of(image1), Samples::of(image2));
+    let rows = par::map_ranges(samples1.len(), par::row_len(image1), |range| {
+        let start = range.start;
+        let mut sum = 0.0;
+        samples1
+            .slice(range.clone())
+            .for_each_pair(&samples2.slice(range), |i, p1, p2| {
+                if !mask.is_ignored((start + i) / channels as usize) {
+                    sum += (p1 - p2).abs();
+                }
+            });
+        sum
+    });
+    // Summed by row, like `calculate_diff_ratio`
+    let diffsum: f64 = rows.iter().sum();
+
+    diffsum / (max_channel_value(image1, image2) * included as f64)
+}
+
+/// Return the difference ratio of each region, in order, see `calculate_diff_ratio`.
+/// Regions are clipped to the images.
+pub fn calculate_region_diff_ratios(
+    image1: &DynamicImage,
+    image2: &DynamicImage,
+    regions: &[Region],
+) -> Result<Vec<f64>, DiffError> {
+    regions
+        .iter()
+        .map(|region| {
+            let rect = region.clip(image1)?;
+            Ok(calculate_diff_ratio(
+                &crop(image1, rect),
+                &crop(image2, rect),
+            ))
+        })
+        .collect()
+}
+
+/// How many pixels of an image pair differ
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PixelCount {
+    pub differing: u64,
+    pub total: u64,
+}
+
+impl PixelCount {
+    /// Differing pixels as a percentage of all pixels
+    pub fn percentage(&self) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            100.0 * self.differing as f64 / self.total as f64
+        }
+    }
+}
+
+/// Count the pixels where at least one channel differs by more than `tolerance`.
+///
+/// The tolerance is on the 8-bit scale and is scaled up for 16-bit and
+/// floating-point images.
+pub fn count_diff_pixels(
+    image1: &DynamicImage,
+    image2: &DynamicImage,
+    tolerance: u8,
+) -> PixelCount {
+    count_pixels(image1, image2, tolerance, None)
+}
 
-    ratio
+/// Like `count_diff_pixels`, but ignored pixels are neither counted as differing
+/// nor included in the total
+pub fn count_masked_diff_pixels(
+    image1: &DynamicImage,
+    image2: &DynamicImage,
+    tolerance: u8,
+    mask: &Mask,
+) -> PixelCount {
+    count_pixels(image1, image2, tolerance, Some(mask))
+}
+
+fn count_pixels(
+    image1: &DynamicImage,
+    image2: &DynamicImage,
+    tolerance: u8,
+    mask: Option<&Mask>,
+) -> PixelCount {
+    let total = match mask {
+        Some(mask) => mask.included_count(),
+        None => u64::from(image1.width()) * u64::from(image1.height()),
+    };
+    let changed = changed_pixels(image1, image2, tolerance, mask);
+    let differing = changed.iter().filter(|&&c| c).count() as u64;
+
+    PixelCount { differing, total }
+}
+
+/// For every pixel in row-major order, whether a channel differs by more than
+/// `tolerance` (on the 8-bit scale) and the mask does not ignore it
+pub(crate) fn changed_pixels(
+    image1: &DynamicImage,
+    image2: &DynamicImage,
+    tolerance: u8,
+    mask: Option<&Mask>,
+) -> Vec<bool> {
+    let tolerance = f64::from(tolerance) * max_channel_value(image1, image2) / 255.0;
+    let channels = usize::from(image1.color().channel_count());
+
+    let (samples1, samples2) = (Samples::of(image1), Samples::of(image2));
+    let rows = par::map_ranges(samples1.len(), par::row_len(image1), |range| {
+        let mut changed = vec![false; range.len() / channels];
+        samples1
+            .slice(range.clone())
+            .for_each_pair(&samples2.slice(range), |i, p1, p2| {
+                if (p1 - p2).abs() > tolerance {
+                    changed[i / channels] = true;
+                }
+            });
+        changed
+    });
+    let mut changed = rows.concat();
+    if let Some(mask) = mask {
+        for (i, c) in changed.iter_mut().enumerate() {
+            *c = *c && !mask.is_ignored(i);
+        }
+    }
+    changed
+}
+
+/// Apply `f` to every pair of channel values of two equally sized buffers
+fn map_buffers<P: Pixel>(
+    buffer1: &ImageBuffer<P, Vec<P::Subpixel>>,
+    buffer2: &ImageBuffer<P, Vec<P::Subpixel>>,
+    f: impl Fn(P::Subpixel, P::Subpixel) -> P::Subpixel + Sync + Send,
+) -> ImageBuffer<P, Vec<P::Subpixel>>
+where
+    P::Subpixel: Sync + Send,
+{
+    let (w, h) = buffer1.dimensions();
+    let raw = par::zip_map(buffer1.as_raw(), buffer2.as_raw(), f);
+    // unwrap() is safe: the buffer has exactly as many channels as the input
+    ImageBuffer::from_raw(w, h, raw).unwrap()
+}
+
+/// Return an image that is the per-channel absolute difference of the two images
+/// given, in the same color mode and bit depth as the inputs
+pub fn diff_image(image1: &DynamicImage, image2: &DynamicImage) -> DynamicImage {
+    use DynamicImage::*;
+
+    let u8_diff = |a: u8, b: u8| a.abs_diff(b);
+    let u16_diff = |a: u16, b: u16| a.abs_diff(b);
+    let f32_diff = |a: f32, b: f32| (a - b).abs();
+    match (image1, image2) {
+        (ImageLuma8(b1), ImageLuma8(b2)) => ImageLuma8(map_buffers(b1, b2, u8_diff)),
+        (ImageLumaA8(b1), ImageLumaA8(b2)) => ImageLumaA8(map_buffers(b1, b2, u8_diff)),
+        (ImageRgb8(b1), ImageRgb8(b2)) => ImageRgb8(map_buffers(b1, b2, u8_diff)),
+        (ImageRgba8(b1), ImageRgba8(b2)) => ImageRgba8(map_buffers(b1, b2, u8_diff)),
+        (ImageLuma16(b1), ImageLuma16(b2)) => ImageLuma16(map_buffers(b1, b2, u16_diff)),
+        (ImageLumaA16(b1), ImageLumaA16(b2)) => ImageLumaA16(map_buffers(b1, b2, u16_diff)),
+        (ImageRgb16(b1), ImageRgb16(b2)) => ImageRgb16(map_buffers(b1, b2, u16_diff)),
+        (ImageRgba16(b1), ImageRgba16(b2)) => ImageRgba16(map_buffers(b1, b2, u16_diff)),
+        (ImageRgb32F(b1), ImageRgb32F(b2)) => ImageRgb32F(map_buffers(b1, b2, f32_diff)),
+        // Anything else, including mismatched color types, is compared in RGBA f32
+        _ => ImageRgba32F(map_buffers(
+            &image1.to_rgba32f(),
+            &image2.to_rgba32f(),
+            f32_diff,
+        )),
+    }
+}
+
+/// Write an image to the given filename
+pub fn save_image(image: &DynamicImage, filename: &str) -> Result<(), DiffError> {
+    image.save(filename).map_err(|source| DiffError::Save {
+        path: filename.to_string(),
+        source,
+    })
 }
 
 /// Create an image that is the difference of the two images given, and write to the given filename
 pub fn create_diff_image(
-    image1: DynamicImage,
-    image2: DynamicImage,
+    image1: &DynamicImage,
+    image2: &DynamicImage,
     filename: &str,
-) -> Result<(), String> {
-    let w = image1.width();
-    let h = image1.height();
-
-    let mut diff = match image1.color() {
-        image::ColorType::RGB(_) => image::DynamicImage::new_rgb8(w, h),
-        image::ColorType::RGBA(_) => image::DynamicImage::new_rgba8(w, h),
-        _ => return Err(format!("color mode {:?} not yet supported", image1.color())),
+) -> Result<(), DiffError> {
+    save_image(&diff_image(image1, image2), filename)
+}
+
+/// Render the SSIM map of two images, and write to the given filename
+pub fn create_ssim_image(map: &SsimMap, filename: &str) -> Result<(), DiffError> {
+    save_image(&DynamicImage::ImageLuma8(map.to_image()), filename)
+}
+
+/// Render the ΔE map of two images, and write to the given filename
+pub fn create_delta_e_image(map: &DeltaEMap, filename: &str) -> Result<(), DiffError> {
+    save_image(&DynamicImage::ImageLuma8(map.to_image()), filename)
+}
+
+/// The file name of a path, to label an image with
+fn file_label(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_string())
+}
+
+/// Render the diff image in the configured style. The SSIM or ΔE map, when given,
+/// takes the place of the absolute difference.
+fn render_diff(
+    config: &Config,
+    (image1, image2): (&DynamicImage, &DynamicImage),
+    mask: Option<&Mask>,
+    antialiased: Option<&Mask>,
+    ssim: Option<&SsimMap>,
+    delta_e: Option<&DeltaEMap>,
+) -> DynamicImage {
+    let (width, height) = image1.dimensions();
+    let mut legend = None;
+    let diff = match config.style {
+        DiffStyle::Overlay => {
+            let changed = match delta_e {
+                Some(map) => map.changed(
+                    config
+                        .delta_e_tolerance
+                        .unwrap_or(DEFAULT_DELTA_E_TOLERANCE),
+                ),
+                None => changed_pixels(image1, image2, config.pixel_tolerance.unwrap_or(0), mask),
+            };
+            let highlight = config.highlight_color.unwrap_or(DEFAULT_HIGHLIGHT_COLOR);
+            DynamicImage::ImageRgba8(overlay(image1, &changed, highlight))
+        }
+        DiffStyle::Heatmap => {
+            let (values, default_max) = if let Some(map) = ssim {
+                let dissimilarity = map.values.iter().map(|v| (1.0 - v).clamp(0.0, 1.0));
+                (dissimilarity.collect(), 1.0)
+            } else if let Some(map) = delta_e {
+                (map.values.clone(), deltae::VISUAL_MAX)
+            } else {
+                (difference_magnitude(image1, image2), 1.0)
+            };
+            let range = (
+                config.heatmap_min.unwrap_or(0.0),
+                config.heatmap_max.unwrap_or(default_max),
+            );
+            if config.legend {
+                legend = Some(range);
+            }
+            let image = heatmap(&values, width, height, config.colormap, range);
+            DynamicImage::ImageRgba8(image)
+        }
+        DiffStyle::Absolute => {
+            if let Some(map) = ssim {
+                DynamicImage::ImageLuma8(map.to_image())
+            } else if let Some(map) = delta_e {
+                DynamicImage::ImageLuma8(map.to_image())
+            } else {
+                config.quantization.apply(&diff_image(image1, image2))
+            }
+        }
     };
+    let diff = match config.diff_color {
+        Some(color) => color.convert(&diff),
+        None => diff,
+    };
+    let diff = match mask {
+        Some(mask) => mask.paint(&diff, config.mask_color.unwrap_or(DEFAULT_MASK_COLOR)),
+        None => diff,
+    };
+    let diff = match antialiased {
+        Some(pixels) => pixels.paint(
+            &diff,
+            config
+                .antialiased_color
+                .unwrap_or(DEFAULT_ANTIALIASED_COLOR),
+        ),
+        None => diff,
+    };
+    // The legend goes below the image, so it is added last
+    match legend {
+        Some(range) => {
+            DynamicImage::ImageRgba8(with_legend(&diff.to_rgba8(), config.colormap, range))
+        }
+        None => diff,
+    }
+}
 
-    for x in 0..w {
-        for y in 0..h {
-            let mut rgba = [0; 4];
-            for c in 0..4 {
-                rgba[c] = abs_diff(
-                    image1.get_pixel(x, y).data[c],
-                    image2.get_pixel(x, y).data[c],
-                );
+/// Build the mask from the ignore rectangles and mask image of the configuration,
+/// or `None` if neither is given
+fn load_mask(config: &Config, image: &DynamicImage) -> Result<Option<Mask>, DiffError> {
+    let (width, height) = image.dimensions();
+    let mut mask = match config.mask {
+        Some(path) => {
+            let mask = Mask::from_image(&safe_load_image(path)?);
+            if (mask.width, mask.height) != (width, height) {
+                return Err(DiffError::MaskSize {
+                    mask: (mask.width, mask.height),
+                    image: (width, height),
+                });
             }
-            let new_pix = image::Pixel::from_slice(&rgba);
-            diff.put_pixel(x, y, *new_pix);
+            mask
         }
+        None if config.ignore.is_empty() => return Ok(None),
+        None => Mask::new(width, height),
+    };
+    for &rect in &config.ignore {
+        mask.ignore(rect);
     }
+    Ok(Some(mask))
+}
 
-    if let Err(msg) = diff.save(filename) {
-        return Err(msg.to_string());
+/// Run the appropriate diffing process given the configuration settings, and
+/// print the results in the configured format
+pub fn run(config: Config) -> Result<Verdict, DiffError> {
+    let report = compare(&config)?;
+    match config.format {
+        OutputFormat::Text => report.print_text(),
+        OutputFormat::Json => println!("{}", report.to_json()),
     }
+    // unwrap() is safe: compare() always reaches a verdict when it succeeds
+    Ok(report.verdict.unwrap())
+}
 
-    Ok(())
+/// When `compare_writing` writes the diff image to `config.filename`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WriteDiff {
+    /// Whatever the verdict
+    Always,
+    /// Only when the images fail the configured limits, creating the directories
+    /// leading to the file first
+    IfFailed,
 }
 
-/// Run the appropriate diffing process given the configuration settings
-pub fn run(config: Config) -> Result<(), String> {
-    let image1 = safe_load_image(&config.image1)?;
-    let image2 = safe_load_image(&config.image2)?;
+/// Compare the images as configured, writing the requested output files, and
+/// return what was found without printing anything
+pub fn compare(config: &Config) -> Result<Report, DiffError> {
+    compare_writing(config, WriteDiff::Always)
+}
+
+/// Like `compare`, but write the diff image only as `write_diff` says
+pub(crate) fn compare_writing(config: &Config, write_diff: WriteDiff) -> Result<Report, DiffError> {
+    let mut image1 = safe_load_image(config.image1)?;
+    let mut image2 = safe_load_image(config.image2)?;
+    if let Some(color) = config.normalize_color {
+        image1 = normalize_color(&image1, color);
+        image2 = normalize_color(&image2, color);
+    }
+    let mut report = Report::new(
+        ImageInfo::of(config.image1, &image1),
+        ImageInfo::of(config.image2, &image2),
+        config.metric,
+    );
+    let padding = config
+        .size_mismatch
+        .padding(image1.dimensions(), image2.dimensions());
+    // A composite shows the inputs as they are, before any resizing
+    let originals = config.composite.map(|_| (image1.clone(), image2.clone()));
+    if let Some((resized1, resized2)) = config.size_mismatch.apply(&image1, &image2)? {
+        image1 = resized1;
+        image2 = resized2;
+        report.size_mismatch = Some(config.size_mismatch.to_string());
+    }
     validate_image_compatibility(&image1, &image2)?;
+    report.width = Some(image1.width());
+    report.height = Some(image1.height());
+    // The animation and the onion skin show the images as they look, before the
+    // alpha handling and before ignored pixels are made equal
+    let aligned = config
+        .blink
+        .or(config.onion_skin)
+        .map(|_| (image1.clone(), image2.clone()));
+    if let Some((flat1, flat2)) = config.alpha.apply(&image1, &image2) {
+        image1 = flat1;
+        image2 = flat2;
+    }
 
-    match config.filename {
-        Some(filename) => match create_diff_image(image1, image2, filename) {
-            Ok(_) => {
-                println!("Wrote diff image to {}", filename);
-                Ok(())
+    let mut regions = config.regions.clone();
+    if let Some(path) = config.region_file {
+        regions.extend(load_regions(path)?);
+    }
+    let rects = regions
+        .iter()
+        .map(|region| region.clip(&image1))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // The report shows the images as compared, but before ignored pixels are
+    // made equal
+    let shown = config.html_report.map(|_| (image1.clone(), image2.clone()));
+
+    let mask = load_mask(config, &image1)?;
+    if let Some(ref mask) = mask {
+        // Every other metric sees the ignored pixels as equal
+        image2 = mask.neutralize(&image1, &image2);
+        report.ignored_pixels = Some(mask.ignored_count());
+    }
+    let antialiased = if config.ignore_antialiasing {
+        let tolerance = config.pixel_tolerance.unwrap_or(0);
+        let antialiased = find_antialiased(&image1, &image2, tolerance);
+        image2 = antialiased.neutralize(&image1, &image2);
+        report.antialiased_pixels = Some(antialiased.ignored_count());
+        Some(antialiased)
+    } else {
+        None
+    };
+
+    // The SSIM and ΔE maps are both the diff image and the source of the metric.
+    // With regions, the metric is computed per region instead.
+    let rendered = config.filename.is_some() || config.html_report.is_some();
+    let whole_image = rendered || regions.is_empty();
+    let ssim = match config.metric {
+        Metric::Ssim if whole_image => Some(ssim_map(&image1, &image2)),
+        Metric::MsSsim if rendered => Some(ssim_map(&image1, &image2)),
+        _ => None,
+    };
+    let delta_e = match config.metric {
+        Metric::DeltaE(formula) if whole_image => Some(delta_e_map(&image1, &image2, formula)),
+        _ => None,
+    };
+
+    let (aligned1, aligned2) = match aligned {
+        Some((ref aligned1, ref aligned2)) => (aligned1, aligned2),
+        None => (&image1, &image2),
+    };
+    if let Some(path) = config.blink {
+        let diff = if config.blink_diff {
+            Some(render_diff(
+                config,
+                (&image1, &image2),
+                mask.as_ref(),
+                antialiased.as_ref(),
+                ssim.as_ref(),
+                delta_e.as_ref(),
+            ))
+        } else {
+            None
+        };
+        let frames = blink_frames(aligned1, aligned2, diff.as_ref());
+        let delay = config.frame_delay.unwrap_or(DEFAULT_FRAME_DELAY_MS);
+        save_animation(&frames, delay, path)?;
+        report.outputs.blink = Some(path.to_string());
+    }
+    if let Some(path) = config.onion_skin {
+        let opacity = config.onion_opacity.unwrap_or(DEFAULT_ONION_OPACITY);
+        let blended = onion_skin(aligned1, aligned2, opacity);
+        save_image(&DynamicImage::ImageRgba8(blended), path)?;
+        report.outputs.onion_skin = Some(path.to_string());
+    }
+
+    if config.stats {
+        report.stats = Some(calculate_channel_stats(&image1, &image2));
+    }
+
+    let boxes = if config.boxes || config.boxes_image.is_some() || config.html_report.is_some() {
+        find_changed_boxes(
+            &image1,
+            &image2,
+            config.pixel_tolerance.unwrap_or(0),
+            config.merge_distance.unwrap_or(DEFAULT_MERGE_DISTANCE),
+            mask.as_ref(),
+        )
+    } else {
+        Vec::new()
+    };
+    if let Some(path) = config.boxes_image {
+        save_image(&DynamicImage::ImageRgba8(draw_boxes(&image1, &boxes)), path)?;
+        report.outputs.boxes = Some(path.to_string());
+    }
+    if config.boxes {
+        report.boxes = Some(boxes.clone());
+    }
+
+    let reporting = config.threshold.is_some()
+        || config.max_pixels.is_some()
+        || config.pixel_tolerance.is_some()
+        || config.html_report.is_some();
+    if config.filename.is_some() && !reporting {
+        report.verdict = Some(Verdict::Pass);
+    } else {
+        if regions.is_empty() {
+            report.measurements.push(evaluate(
+                config,
+                None,
+                (&image1, &image2),
+                (mask.as_ref(), padding.as_ref()),
+                ssim.as_ref(),
+                delta_e.as_ref(),
+            ));
+        }
+        for (region, &rect) in regions.iter().zip(&rects) {
+            let region_mask = mask.as_ref().map(|mask| mask.crop(rect));
+            let region_padding = padding.as_ref().map(|padding| padding.crop(rect));
+            let images = (&crop(&image1, rect), &crop(&image2, rect));
+            report.measurements.push(evaluate(
+                config,
+                Some(region),
+                images,
+                (region_mask.as_ref(), region_padding.as_ref()),
+                None,
+                None,
+            ));
+        }
+        let failed = report
+            .measurements
+            .iter()
+            .any(|m| m.verdict == Verdict::Fail);
+        report.verdict = Some(if failed { Verdict::Fail } else { Verdict::Pass });
+    }
+
+    // In a directory comparison, only the diffs of changed files are written
+    let filename = config
+        .filename
+        .filter(|_| write_diff == WriteDiff::Always || report.verdict == Some(Verdict::Fail));
+    let diff = filename.or(config.html_report).map(|_| {
+        render_diff(
+            config,
+            (&image1, &image2),
+            mask.as_ref(),
+            antialiased.as_ref(),
+            ssim.as_ref(),
+            delta_e.as_ref(),
+        )
+    });
+    if let (Some(filename), Some(diff)) = (filename, &diff) {
+        if let (WriteDiff::IfFailed, Some(dir)) = (write_diff, Path::new(filename).parent()) {
+            fs::create_dir_all(dir).map_err(|source| DiffError::CreateDir {
+                path: dir.to_string_lossy().into_owned(),
+                source,
+            })?;
+        }
+        match (config.composite, originals) {
+            (Some(options), Some((original1, original2))) => {
+                let mut panels = vec![
+                    Panel {
+                        image: &original1,
+                        label: file_label(config.image1),
+                    },
+                    Panel {
+                        image: &original2,
+                        label: file_label(config.image2),
+                    },
+                ];
+                if options.include_diff {
+                    let (value, _) = measure(
+                        config,
+                        (&image1, &image2),
+                        (mask.as_ref(), padding.as_ref()),
+                        ssim.as_ref(),
+                        delta_e.as_ref(),
+                    );
+                    panels.push(Panel {
+                        image: diff,
+                        label: format!("{}: {:.6}", config.metric, value),
+                    });
+                }
+                let out = composite(&panels, options.layout, options.gutter);
+                save_image(&DynamicImage::ImageRgba8(out), filename)?;
+            }
+            _ => save_image(diff, filename)?,
+        }
+        report.outputs.diff = Some(filename.to_string());
+    }
+
+    if let (Some(path), Some((shown1, shown2)), Some(diff)) = (config.html_report, shown, diff) {
+        let save_error = |source| DiffError::Save {
+            path: path.to_string(),
+            source,
+        };
+        let html = html_report(&report, (&shown1, &shown2), &diff, &boxes).map_err(save_error)?;
+        fs::write(path, html).map_err(|e| save_error(ImageError::IoError(e)))?;
+        report.outputs.report = Some(path.to_string());
+    }
+    Ok(report)
+}
+
+/// Padding always counts as differing, so it is measured apart from the rest of
+/// the images. Returns the mask to measure the rest with (`None` when there is
+/// no padding), and the number of padded pixels the mask does not ignore.
+fn without_padding(mask: Option<&Mask>, padding: Option<&Mask>) -> (Option<Mask>, u64) {
+    match padding {
+        Some(padding) => {
+            let rest = padding.union(mask);
+            let ignored = mask.map_or(0, |mask| mask.ignored_count());
+            let padded = rest.ignored_count() - ignored;
+            (Some(rest), padded)
+        }
+        None => (None, 0),
+    }
+}
+
+/// Compute the configured metric of an image pair, and its per-channel values
+/// for MSE, RMSE and PSNR. The SSIM and ΔE maps are computed unless given.
+fn measure(
+    config: &Config,
+    (image1, image2): (&DynamicImage, &DynamicImage),
+    (mask, padding): (Option<&Mask>, Option<&Mask>),
+    ssim: Option<&SsimMap>,
+    delta_e: Option<&DeltaEMap>,
+) -> (f64, Option<Vec<f64>>) {
+    match config.metric {
+        Metric::Ratio => match without_padding(mask, padding) {
+            (Some(rest), padded) => {
+                // Padded pixels differ fully, the rest as measured
+                let measured = rest.included_count();
+                let ratio = calculate_masked_diff_ratio(image1, image2, &rest);
+                let total = measured + padded;
+                if total == 0 {
+                    (0.0, None)
+                } else {
+                    let sum = ratio * measured as f64 + padded as f64;
+                    (sum / total as f64, None)
+                }
             }
-            Err(msg) => Err(msg),
+            (None, _) => match mask {
+                Some(mask) => (calculate_masked_diff_ratio(image1, image2, mask), None),
+                None => (calculate_diff_ratio(image1, image2), None),
+            },
         },
-        None => {
-            let ratio = calculate_diff_ratio(image1, image2);
-            println!("{}", ratio);
-            return Ok(());
+        // Ignored pixels are left out of the means, like for the ratio
+        Metric::Ssim => match (ssim, mask) {
+            (Some(map), Some(mask)) => (map.masked_mean(mask), None),
+            (Some(map), None) => (map.mean(), None),
+            (None, Some(mask)) => (ssim_map(image1, image2).masked_mean(mask), None),
+            (None, None) => (calculate_ssim(image1, image2), None),
+        },
+        Metric::MsSsim => (calculate_ms_ssim(image1, image2), None),
+        Metric::Mse | Metric::Rmse | Metric::Psnr => {
+            let errors = match mask {
+                Some(mask) => calculate_masked_error_metrics(image1, image2, mask),
+                None => calculate_error_metrics(image1, image2),
+            };
+            match config.metric {
+                Metric::Mse => (errors.mse, Some(errors.channel_mse)),
+                Metric::Rmse => (errors.rmse, Some(errors.channel_rmse)),
+                _ => (errors.psnr, Some(errors.channel_psnr)),
+            }
+        }
+        Metric::DeltaE(formula) => {
+            let computed;
+            let map = match delta_e {
+                Some(map) => map,
+                None => {
+                    computed = delta_e_map(image1, image2, formula);
+                    &computed
+                }
+            };
+            match mask {
+                Some(mask) => (map.masked_mean(mask), None),
+                None => (map.mean(), None),
+            }
         }
     }
 }
+
+/// Compute the metric and pixel count of an image pair (or a region of it) and
+/// check them against the configured limits. The SSIM and ΔE maps are computed
+/// unless given.
+fn evaluate(
+    config: &Config,
+    region: Option<&Region>,
+    (image1, image2): (&DynamicImage, &DynamicImage),
+    (mask, padding): (Option<&Mask>, Option<&Mask>),
+    ssim: Option<&SsimMap>,
+    delta_e: Option<&DeltaEMap>,
+) -> Measurement {
+    let computed;
+    let delta_e = match (config.metric, delta_e) {
+        (Metric::DeltaE(_), Some(map)) => Some(map),
+        (Metric::DeltaE(formula), None) => {
+            computed = delta_e_map(image1, image2, formula);
+            Some(&computed)
+        }
+        _ => None,
+    };
+
+    let (value, channels) = measure(config, (image1, image2), (mask, padding), ssim, delta_e);
+    let mut measurement = Measurement {
+        region: region.cloned(),
+        value,
+        channels,
+        pixels: None,
+        delta_e_tolerance: None,
+        verdict: Verdict::Pass,
+    };
+
+    if let Some(threshold) = config.threshold {
+        if !config.metric.within(value, threshold) {
+            measurement.verdict = Verdict::Fail;
+        }
+    }
+    // Padded pixels are counted apart from the rest, and always differ
+    let (rest, padded) = without_padding(mask, padding);
+    let mask = rest.as_ref().or(mask);
+    if let Some(map) = delta_e {
+        let tolerance = config
+            .delta_e_tolerance
+            .unwrap_or(DEFAULT_DELTA_E_TOLERANCE);
+        // Like `count_masked_diff_pixels`, ignored pixels are left out of the total
+        measurement.pixels = Some(match mask {
+            Some(mask) => PixelCount {
+                differing: map.count_masked_above(tolerance, mask),
+                total: mask.included_count(),
+            },
+            None => PixelCount {
+                differing: map.count_above(tolerance),
+                total: u64::from(image1.width()) * u64::from(image1.height()),
+            },
+        });
+        measurement.delta_e_tolerance = Some(tolerance);
+    } else if config.max_pixels.is_some() || config.pixel_tolerance.is_some() {
+        let tolerance = config.pixel_tolerance.unwrap_or(0);
+        measurement.pixels = Some(match mask {
+            Some(mask) => count_masked_diff_pixels(image1, image2, tolerance, mask),
+            None => count_diff_pixels(image1, image2, tolerance),
+        });
+    }
+    if let Some(ref mut count) = measurement.pixels {
+        count.differing += padded;
+        count.total += padded;
+    }
+    if let (Some(count), Some(max_pixels)) = (measurement.pixels, config.max_pixels) {
+        if count.differing > max_pixels {
+            measurement.verdict = Verdict::Fail;
+        }
+    }
+
+    measurement
+}
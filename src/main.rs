@@ -1,10 +1,109 @@
 extern crate clap;
 
+use std::path::Path;
 use std::process::exit;
 
-use clap::{App, Arg};
+use clap::{App, Arg, ErrorKind};
 
-use diffimg::Config;
+use diffimg::{Config, DiffError, OutputFormat, Report, Verdict};
+
+/// Exit code used when the images differ by more than the allowed tolerance
+const EXIT_DIFFERENT: i32 = 1;
+
+/// Exit code used when the command line is invalid, as `EX_USAGE` in sysexits.h.
+/// clap would exit with 1, which could not be told apart from differing images.
+const EXIT_USAGE: i32 = 64;
+
+/// Map each kind of failure to its own exit code so scripts can tell them apart
+fn exit_code(err: &DiffError) -> i32 {
+    match err {
+        DiffError::MissingFile(_) => 2,
+        DiffError::Decode { .. } => 3,
+        DiffError::DimensionMismatch { .. } => 4,
+        DiffError::ColorMismatch { .. } => 5,
+        DiffError::Save { .. } => 7,
+        DiffError::MaskSize { .. } => 8,
+        DiffError::RegionFile { .. } => 9,
+        DiffError::ListDir { .. } => 10,
+        DiffError::RegionOutside { .. } => 11,
+        DiffError::CreateDir { .. } => 12,
+    }
+}
+
+fn is_non_negative(v: String) -> Result<(), String> {
+    match v.parse::<f64>() {
+        Ok(n) if n >= 0.0 => Ok(()),
+        _ => Err(format!("\"{}\" is not a non-negative number", v)),
+    }
+}
+
+fn is_number(v: String) -> Result<(), String> {
+    match v.parse::<f64>() {
+        Ok(n) if n.is_finite() => Ok(()),
+        _ => Err(format!("\"{}\" is not a number", v)),
+    }
+}
+
+fn is_count(v: String) -> Result<(), String> {
+    match v.parse::<u64>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("\"{}\" is not a non-negative integer", v)),
+    }
+}
+
+fn is_channel_value(v: String) -> Result<(), String> {
+    match v.parse::<u8>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("\"{}\" is not an integer between 0 and 255", v)),
+    }
+}
+
+fn is_distance(v: String) -> Result<(), String> {
+    match v.parse::<u32>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("\"{}\" is not a distance in pixels", v)),
+    }
+}
+
+fn is_gutter(v: String) -> Result<(), String> {
+    match v.parse::<u32>() {
+        Ok(n) if n <= diffimg::MAX_GUTTER => Ok(()),
+        _ => Err(format!(
+            "\"{}\" is not a gutter between 0 and {} pixels",
+            v,
+            diffimg::MAX_GUTTER
+        )),
+    }
+}
+
+fn is_jobs(v: String) -> Result<(), String> {
+    match v.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(()),
+        _ => Err(format!("\"{}\" is not a positive number of jobs", v)),
+    }
+}
+
+fn is_delay(v: String) -> Result<(), String> {
+    match v.parse::<u16>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("\"{}\" is not a delay between 0 and 65535 ms", v)),
+    }
+}
+
+fn is_fraction(v: String) -> Result<(), String> {
+    match v.parse::<f64>() {
+        Ok(n) if (0.0..=1.0).contains(&n) => Ok(()),
+        _ => Err(format!("\"{}\" is not a number between 0 and 1", v)),
+    }
+}
+
+fn is_color(v: String) -> Result<(), String> {
+    diffimg::parse_color(&v).map(|_| ())
+}
+
+fn is_rect(v: String) -> Result<(), String> {
+    v.parse::<diffimg::Rect>().map(|_| ())
+}
 
 fn main() {
     let matches = App::new("diffimg")
@@ -13,30 +112,413 @@ fn main() {
         .about("Calculate the ratio difference of an image, or generate a diff image.")
         .arg(
             Arg::with_name("image1")
-                .help("First image to diff")
+                .help("First image to diff, or a directory of images to compare with the second")
                 .index(1)
                 .required(true),
         )
         .arg(
             Arg::with_name("image2")
-                .help("Second image to diff")
+                .help("Second image to diff, or a directory of images")
                 .index(2)
                 .required(true),
         )
+        .arg(
+            Arg::with_name("normalize_color")
+                .help(
+                    "Convert both images to a common color type before comparing (rgba8 \
+                     unless --normalize-to is given). Images without alpha are treated as \
+                     fully opaque.",
+                )
+                .long("normalize-color"),
+        )
+        .arg(
+            Arg::with_name("normalize_to")
+                .help("The color type --normalize-color converts to; implies --normalize-color.")
+                .long("normalize-to")
+                .takes_value(true)
+                .possible_values(&[
+                    "l8", "la8", "rgb8", "rgba8", "l16", "la16", "rgb16", "rgba16", "rgb32f",
+                    "rgba32f",
+                ]),
+        )
+        .arg(
+            Arg::with_name("size_mismatch")
+                .help(
+                    "What to do if the images have different dimensions: fail, crop both to \
+                     the common top-left area, pad the smaller one (padding counts as \
+                     difference), or resize the second image to the first.",
+                )
+                .long("size-mismatch")
+                .takes_value(true)
+                .possible_values(&["error", "crop", "pad", "resize"])
+                .default_value("error"),
+        )
+        .arg(
+            Arg::with_name("pad_color")
+                .help("Color used by --size-mismatch pad, as r,g,b[,a] or #rrggbb[aa] (default transparent black).")
+                .long("pad-color")
+                .takes_value(true)
+                .validator(is_color),
+        )
+        .arg(
+            Arg::with_name("resize_filter")
+                .help("Filter used by --size-mismatch resize.")
+                .long("resize-filter")
+                .takes_value(true)
+                .possible_values(&["nearest", "triangle", "catmullrom", "gaussian", "lanczos3"])
+                .default_value("lanczos3"),
+        )
+        .arg(
+            Arg::with_name("alpha")
+                .help(
+                    "How to compare the alpha channel: as raw values, by drawing both images \
+                     over --alpha-background, treating pixels transparent in both images as \
+                     equal, or not at all.",
+                )
+                .long("alpha")
+                .takes_value(true)
+                .possible_values(&["raw", "composite", "transparent", "ignore"])
+                .default_value("raw"),
+        )
+        .arg(
+            Arg::with_name("alpha_background")
+                .help("Background used by --alpha composite, as r,g,b or #rrggbb (default white).")
+                .long("alpha-background")
+                .takes_value(true)
+                .validator(is_color),
+        )
+        .arg(
+            Arg::with_name("ignore")
+                .help("Leave the rectangle x,y,width,height out of the comparison. Can be repeated.")
+                .long("ignore")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(is_rect),
+        )
+        .arg(
+            Arg::with_name("mask")
+                .help("Leave the pixels that are black in this image out of the comparison.")
+                .long("mask")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mask_color")
+                .help("Color ignored pixels are drawn in on the diff image, as r,g,b[,a] or #rrggbb[aa] (default magenta).")
+                .long("mask-color")
+                .takes_value(true)
+                .validator(is_color),
+        )
+        .arg(
+            Arg::with_name("ignore_antialiasing")
+                .help("Treat differing pixels that look like anti-aliased edges in either image as equal.")
+                .long("ignore-antialiasing"),
+        )
+        .arg(
+            Arg::with_name("antialiased_color")
+                .help("Color of anti-aliased pixels on the diff image, as r,g,b[,a] or #rrggbb[aa] (default yellow).")
+                .long("antialiased-color")
+                .takes_value(true)
+                .validator(is_color),
+        )
+        .arg(
+            Arg::with_name("region")
+                .help("Compare only the rectangle x,y,width,height and report it separately. Can be repeated.")
+                .long("region")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(is_rect),
+        )
+        .arg(
+            Arg::with_name("region_file")
+                .help("Compare only the named regions in this file, one \"name x,y,width,height\" per line.")
+                .long("regions")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("filename")
-                .help("If present, save a diff image to this filename.")
+                .help("If present, save a diff image to this filename. When comparing directories, write the diff images of changed files to this directory.")
                 .short("f")
                 .long("filename")
                 .takes_value(true),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("style")
+                .help(
+                    "What the diff image shows: the absolute difference, a faded copy of the \
+                     first image with changed pixels highlighted, or a heatmap of the \
+                     difference (or the SSIM or ΔE map).",
+                )
+                .long("style")
+                .takes_value(true)
+                .possible_values(&["absolute", "overlay", "heatmap"])
+                .default_value("absolute"),
+        )
+        .arg(
+            Arg::with_name("highlight_color")
+                .help("Color of changed pixels with --style overlay, as r,g,b[,a] or #rrggbb[aa] (default red).")
+                .long("highlight-color")
+                .takes_value(true)
+                .validator(is_color),
+        )
+        .arg(
+            Arg::with_name("colormap")
+                .help("Colors of --style heatmap.")
+                .long("colormap")
+                .takes_value(true)
+                .possible_values(&["viridis", "inferno", "jet"])
+                .default_value("viridis"),
+        )
+        .arg(
+            Arg::with_name("heatmap_min")
+                .help("Value at the low end of the heatmap colors (default 0).")
+                .long("heatmap-min")
+                .takes_value(true)
+                .validator(is_number),
+        )
+        .arg(
+            Arg::with_name("heatmap_max")
+                .help("Value at the high end of the heatmap colors (default 1, or 100 for ΔE).")
+                .long("heatmap-max")
+                .takes_value(true)
+                .validator(is_number),
+        )
+        .arg(
+            Arg::with_name("legend")
+                .help("Add a legend with the colormap and its range below the heatmap.")
+                .long("legend"),
+        )
+        .arg(
+            Arg::with_name("composite")
+                .help(
+                    "Write the inputs and the diff next to each other (horizontal) or above \
+                     each other (vertical), labelled with the file names and the metric, \
+                     instead of the diff alone.",
+                )
+                .long("composite")
+                .takes_value(true)
+                .possible_values(&["horizontal", "vertical"])
+                .requires("filename"),
+        )
+        .arg(
+            Arg::with_name("composite_panels")
+                .help("Show both inputs and the diff (3), or only the inputs (2) in the composite.")
+                .long("composite-panels")
+                .takes_value(true)
+                .possible_values(&["2", "3"])
+                .default_value("3"),
+        )
+        .arg(
+            Arg::with_name("gutter")
+                .help("Space in pixels between and around the panels of the composite (default 10).")
+                .long("gutter")
+                .takes_value(true)
+                .validator(is_gutter),
+        )
+        .arg(
+            Arg::with_name("blink")
+                .help("Write an animation flipping between the images to this file (.gif or .png).")
+                .long("blink")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("blink_diff")
+                .help("Add the diff image as a third frame of the --blink animation.")
+                .long("blink-diff")
+                .requires("blink"),
+        )
+        .arg(
+            Arg::with_name("frame_delay")
+                .help("How long each frame of the --blink animation is shown, in milliseconds (default 500).")
+                .long("frame-delay")
+                .takes_value(true)
+                .validator(is_delay),
+        )
+        .arg(
+            Arg::with_name("onion_skin")
+                .help("Write the second image blended over the first to this file.")
+                .long("onion-skin")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("opacity")
+                .help("Opacity of the second image in the --onion-skin, from 0 to 1 (default 0.5).")
+                .long("opacity")
+                .takes_value(true)
+                .validator(is_fraction),
+        )
+        .arg(
+            Arg::with_name("diff_color")
+                .help("Color mode of the diff image (default: same as the inputs).")
+                .long("diff-color")
+                .takes_value(true)
+                .possible_values(&["gray", "graya", "rgb", "rgba"]),
+        )
+        .arg(
+            Arg::with_name("quantization")
+                .help(
+                    "How to write the diff image of 16-bit or floating-point inputs: scale \
+                     the native range to 8 bits, stretch the largest difference to 8-bit \
+                     white, or keep the native depth.",
+                )
+                .long("quantize")
+                .takes_value(true)
+                .possible_values(&["scale", "stretch", "native"])
+                .default_value("scale"),
+        )
+        .arg(
+            Arg::with_name("metric")
+                .help(
+                    "Metric to compute. With ssim or msssim, -f writes the SSIM map; \
+                     with a ΔE metric (cie76, cie94, ciede2000) it writes the ΔE map.",
+                )
+                .short("m")
+                .long("metric")
+                .takes_value(true)
+                .possible_values(&[
+                    "ratio",
+                    "ssim",
+                    "msssim",
+                    "mse",
+                    "rmse",
+                    "psnr",
+                    "cie76",
+                    "cie94",
+                    "ciede2000",
+                ])
+                .default_value("ratio"),
+        )
+        .arg(
+            Arg::with_name("threshold")
+                .help(
+                    "Exit with code 1 if the metric is above this value \
+                     (below it for ssim, msssim and psnr).",
+                )
+                .short("t")
+                .long("threshold")
+                .takes_value(true)
+                .validator(is_non_negative),
+        )
+        .arg(
+            Arg::with_name("max_pixels")
+                .help("Exit with code 1 if more than this many pixels differ.")
+                .long("max-pixels")
+                .takes_value(true)
+                .validator(is_count),
+        )
+        .arg(
+            Arg::with_name("pixel_tolerance")
+                .help(
+                    "Only count a pixel as different if a channel differs by more than this \
+                     (0-255), and report how many pixels differ.",
+                )
+                .long("pixel-tolerance")
+                .takes_value(true)
+                .validator(is_channel_value),
+        )
+        .arg(
+            Arg::with_name("delta_e_tolerance")
+                .help(
+                    "With a ΔE metric, pixels whose ΔE is at or below this value count as equal \
+                     for --max-pixels (default 2.3).",
+                )
+                .long("delta-e-tolerance")
+                .takes_value(true)
+                .validator(is_non_negative),
+        )
+        .arg(
+            Arg::with_name("stats")
+                .help("Print the mean, maximum, standard deviation and histogram of the differences of each channel.")
+                .long("stats"),
+        )
+        .arg(
+            Arg::with_name("boxes")
+                .help("Print the bounding boxes of the changed areas, with their pixel counts and mean intensity.")
+                .long("boxes"),
+        )
+        .arg(
+            Arg::with_name("merge_distance")
+                .help("Merge changed areas at most this many pixels apart (default 5).")
+                .long("merge-distance")
+                .takes_value(true)
+                .validator(is_distance),
+        )
+        .arg(
+            Arg::with_name("boxes_image")
+                .help("Draw the bounding boxes of the changed areas over the first image and write it to this file.")
+                .long("draw-boxes")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("html_report")
+                .help("Write a self-contained HTML page for reviewing the comparison to this file.")
+                .long("report")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("jobs")
+                .help("When comparing directories, how many pairs to compare at once (default: one per CPU).")
+                .short("j")
+                .long("jobs")
+                .takes_value(true)
+                .validator(is_jobs),
+        )
+        .arg(
+            Arg::with_name("format")
+                .help("Print the results as text, or as one JSON object (also on errors).")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["text", "json"])
+                .default_value("text"),
+        )
+        .get_matches_safe()
+        .unwrap_or_else(|err| match err.kind {
+            ErrorKind::HelpDisplayed | ErrorKind::VersionDisplayed => err.exit(),
+            _ => {
+                eprintln!("{}", err.message);
+                exit(EXIT_USAGE);
+            }
+        });
 
     // We're relying on clap to correctly validate all args,
     // so we shouldn't need to use Result here
     let config = Config::from_clap_matches(&matches);
-    if let Err(msg) = diffimg::run(config) {
-        println!("Error: {:?}", msg);
-        exit(1);
+    let (image1, image2, metric, format) =
+        (config.image1, config.image2, config.metric, config.format);
+    let batch = Path::new(image1).is_dir() && Path::new(image2).is_dir();
+    if batch {
+        // Each of these writes one file, which every pair of images would overwrite
+        let single_files = [
+            ("html_report", "--report"),
+            ("blink", "--blink"),
+            ("onion_skin", "--onion-skin"),
+            ("boxes_image", "--draw-boxes"),
+        ];
+        if let Some((_, option)) = single_files
+            .iter()
+            .find(|(name, _)| matches.is_present(name))
+        {
+            eprintln!("error: {} can't be used when comparing directories", option);
+            exit(EXIT_USAGE);
+        }
+    }
+    let result = if batch {
+        diffimg::run_batch(config)
+    } else {
+        diffimg::run(config)
+    };
+    match result {
+        Ok(Verdict::Pass) => {}
+        Ok(Verdict::Fail) => exit(EXIT_DIFFERENT),
+        Err(err) => {
+            match format {
+                OutputFormat::Text => eprintln!("Error: {}", err),
+                OutputFormat::Json => {
+                    println!("{}", Report::failed(image1, image2, metric, &err).to_json())
+                }
+            }
+            exit(exit_code(&err));
+        }
     };
 }
@@ -0,0 +1,254 @@
+//! Excluding parts of an image from the comparison, e.g. clocks or ad slots that
+//! change on every screenshot.
+
+use std::fmt;
+use std::str::FromStr;
+
+use image::{DynamicImage, GenericImageView, ImageBuffer, Pixel, Rgba};
+use serde::Serialize;
+
+use crate::normalize::color_pixel;
+
+/// The color masked areas are drawn in on a diff image unless another is given
+pub const DEFAULT_MASK_COLOR: Rgba<u8> = Rgba([255, 0, 255, 255]);
+
+/// A rectangle in pixel coordinates, with its origin at the top-left corner
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    /// Whether the pixel at (x, y) lies within the rectangle
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x
+            && y >= self.y
+            && u64::from(x) < u64::from(self.x) + u64::from(self.width)
+            && u64::from(y) < u64::from(self.y) + u64::from(self.height)
+    }
+}
+
+impl fmt::Display for Rect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{},{},{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+impl FromStr for Rect {
+    type Err = String;
+
+    /// Parse a rectangle given as `x,y,width,height`
+    fn from_str(s: &str) -> Result<Rect, String> {
+        let invalid = || format!("\"{}\" is not a rectangle like x,y,width,height", s);
+        let values: Vec<u32> = s
+            .split(',')
+            .map(|v| v.trim().parse().ok())
+            .collect::<Option<_>>()
+            .ok_or_else(invalid)?;
+        match values[..] {
+            [x, y, width, height] => Ok(Rect {
+                x,
+                y,
+                width,
+                height,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Which pixels of an image take part in the comparison
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mask {
+    pub width: u32,
+    pub height: u32,
+    /// One flag per pixel in row-major order, `true` where the pixel is ignored
+    ignored: Vec<bool>,
+}
+
+impl Mask {
+    /// A mask of the given size that ignores nothing
+    pub fn new(width: u32, height: u32) -> Mask {
+        Mask {
+            width,
+            height,
+            ignored: vec![false; width as usize * height as usize],
+        }
+    }
+
+    /// A mask that ignores the pixels flagged `true`, in row-major order
+    pub(crate) fn from_flags(width: u32, height: u32, ignored: Vec<bool>) -> Mask {
+        Mask {
+            width,
+            height,
+            ignored,
+        }
+    }
+
+    /// A mask that ignores the pixels either mask ignores
+    pub(crate) fn union(&self, other: Option<&Mask>) -> Mask {
+        let ignored = match other {
+            Some(other) => self
+                .ignored
+                .iter()
+                .zip(&other.ignored)
+                .map(|(&a, &b)| a || b)
+                .collect(),
+            None => self.ignored.clone(),
+        };
+        Mask::from_flags(self.width, self.height, ignored)
+    }
+
+    /// A mask that ignores the black pixels of `image`
+    pub fn from_image(image: &DynamicImage) -> Mask {
+        let (width, height) = image.dimensions();
+        Mask {
+            width,
+            height,
+            ignored: image.to_luma8().pixels().map(|p| p.0[0] == 0).collect(),
+        }
+    }
+
+    /// Ignore every pixel within `rect`; parts outside the image are skipped
+    pub fn ignore(&mut self, rect: Rect) {
+        let x_end = rect.x.saturating_add(rect.width).min(self.width);
+        let y_end = rect.y.saturating_add(rect.height).min(self.height);
+        for y in rect.y..y_end {
+            for x in rect.x..x_end {
+                self.ignored[(y * self.width + x) as usize] = true;
+            }
+        }
+    }
+
+    /// The part of the mask within `rect`, which must lie inside the mask
+    pub fn crop(&self, rect: Rect) -> Mask {
+        let mut ignored = Vec::with_capacity(rect.width as usize * rect.height as usize);
+        for y in rect.y..rect.y + rect.height {
+            let start = (y * self.width + rect.x) as usize;
+            ignored.extend_from_slice(&self.ignored[start..start + rect.width as usize]);
+        }
+        Mask {
+            width: rect.width,
+            height: rect.height,
+            ignored,
+        }
+    }
+
+    /// Whether the pixel with the given row-major index is ignored
+    pub fn is_ignored(&self, index: usize) -> bool {
+        self.ignored[index]
+    }
+
+    /// The number of ignored pixels
+    pub fn ignored_count(&self) -> u64 {
+        self.ignored.iter().filter(|&&i| i).count() as u64
+    }
+
+    /// The number of pixels that take part in the comparison
+    pub fn included_count(&self) -> u64 {
+        self.ignored.len() as u64 - self.ignored_count()
+    }
+
+    /// Return a copy of `image2` where the ignored pixels are taken from `image1`,
+    /// so that any comparison sees them as equal. Both images must have the color
+    /// type and dimensions of the mask.
+    pub fn neutralize(&self, image1: &DynamicImage, image2: &DynamicImage) -> DynamicImage {
+        use DynamicImage::*;
+
+        let mut out = image2.clone();
+        match (&mut out, image1) {
+            (ImageLuma8(b2), ImageLuma8(b1)) => self.copy_ignored(b2, b1),
+            (ImageLumaA8(b2), ImageLumaA8(b1)) => self.copy_ignored(b2, b1),
+            (ImageRgb8(b2), ImageRgb8(b1)) => self.copy_ignored(b2, b1),
+            (ImageRgba8(b2), ImageRgba8(b1)) => self.copy_ignored(b2, b1),
+            (ImageLuma16(b2), ImageLuma16(b1)) => self.copy_ignored(b2, b1),
+            (ImageLumaA16(b2), ImageLumaA16(b1)) => self.copy_ignored(b2, b1),
+            (ImageRgb16(b2), ImageRgb16(b1)) => self.copy_ignored(b2, b1),
+            (ImageRgba16(b2), ImageRgba16(b1)) => self.copy_ignored(b2, b1),
+            (ImageRgb32F(b2), ImageRgb32F(b1)) => self.copy_ignored(b2, b1),
+            (ImageRgba32F(b2), ImageRgba32F(b1)) => self.copy_ignored(b2, b1),
+            _ => panic!("images must have the same color type"),
+        }
+        out
+    }
+
+    /// Draw the ignored pixels of a diff image in `color`. Grayscale images are
+    /// converted to RGB(A) at the same depth first so that the color shows.
+    pub fn paint(&self, diff: &DynamicImage, color: Rgba<u8>) -> DynamicImage {
+        use DynamicImage::*;
+
+        let mut out = match diff {
+            ImageLuma8(_) => ImageRgb8(diff.to_rgb8()),
+            ImageLumaA8(_) => ImageRgba8(diff.to_rgba8()),
+            ImageLuma16(_) => ImageRgb16(diff.to_rgb16()),
+            ImageLumaA16(_) => ImageRgba16(diff.to_rgba16()),
+            _ => diff.clone(),
+        };
+        let fill = color_pixel(color, out.color());
+        match (&mut out, &fill) {
+            (ImageRgb8(b), ImageRgb8(f)) => self.fill_ignored(b, f[(0, 0)]),
+            (ImageRgba8(b), ImageRgba8(f)) => self.fill_ignored(b, f[(0, 0)]),
+            (ImageRgb16(b), ImageRgb16(f)) => self.fill_ignored(b, f[(0, 0)]),
+            (ImageRgba16(b), ImageRgba16(f)) => self.fill_ignored(b, f[(0, 0)]),
+            (ImageRgb32F(b), ImageRgb32F(f)) => self.fill_ignored(b, f[(0, 0)]),
+            (ImageRgba32F(b), ImageRgba32F(f)) => self.fill_ignored(b, f[(0, 0)]),
+            _ => unreachable!("unknown color type {:?}", out.color()),
+        }
+        out
+    }
+
+    fn copy_ignored<P: Pixel>(
+        &self,
+        dst: &mut ImageBuffer<P, Vec<P::Subpixel>>,
+        src: &ImageBuffer<P, Vec<P::Subpixel>>,
+    ) {
+        for (i, (d, s)) in dst.pixels_mut().zip(src.pixels()).enumerate() {
+            if self.ignored[i] {
+                *d = *s;
+            }
+        }
+    }
+
+    fn fill_ignored<P: Pixel>(&self, buffer: &mut ImageBuffer<P, Vec<P::Subpixel>>, fill: P) {
+        for (i, p) in buffer.pixels_mut().enumerate() {
+            if self.ignored[i] {
+                *p = fill;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_rect() {
+    let rect: Rect = "1,2,3,4".parse().unwrap();
+    assert_eq!(
+        rect,
+        Rect {
+            x: 1,
+            y: 2,
+            width: 3,
+            height: 4
+        }
+    );
+    assert!(rect.contains(1, 2) && rect.contains(3, 5));
+    assert!(!rect.contains(4, 2) && !rect.contains(1, 6));
+    assert!("1,2,3".parse::<Rect>().is_err());
+    assert!("1,2,3,-4".parse::<Rect>().is_err());
+}
+
+#[test]
+fn test_mask_ignore_clips() {
+    let mut mask = Mask::new(4, 4);
+    mask.ignore(Rect {
+        x: 2,
+        y: 3,
+        width: 10,
+        height: 10,
+    });
+    assert_eq!(mask.ignored_count(), 2);
+    assert_eq!(mask.included_count(), 14);
+    assert!(mask.is_ignored(14) && mask.is_ignored(15));
+}
@@ -0,0 +1,104 @@
+//! Mean squared error and the measures derived from it (RMSE, PSNR).
+
+use image::DynamicImage;
+
+use crate::depth::{max_channel_value, Samples};
+use crate::{par, Mask};
+
+/// MSE, RMSE and PSNR of two images, per channel (in the image's channel order)
+/// and combined over all channels. MSE and RMSE are in units of the images'
+/// native depth; PSNR is relative to the largest value that depth can hold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorMetrics {
+    pub channel_mse: Vec<f64>,
+    pub channel_rmse: Vec<f64>,
+    pub channel_psnr: Vec<f64>,
+    pub mse: f64,
+    pub rmse: f64,
+    /// `f64::INFINITY` when the images are identical
+    pub psnr: f64,
+}
+
+/// Peak signal-to-noise ratio in decibels for the given MSE
+fn psnr(mse: f64, max_val: f64) -> f64 {
+    if mse == 0.0 {
+        // Identical images: the noise is zero, so the ratio is unbounded
+        f64::INFINITY
+    } else {
+        10.0 * (max_val * max_val / mse).log10()
+    }
+}
+
+/// Return MSE, RMSE and PSNR of the two images, computed in a single pass
+pub fn calculate_error_metrics(image1: &DynamicImage, image2: &DynamicImage) -> ErrorMetrics {
+    error_metrics(image1, image2, None)
+}
+
+/// Like `calculate_error_metrics`, but ignored pixels are left out of both the
+/// squared errors and the number of values they are averaged over
+pub fn calculate_masked_error_metrics(
+    image1: &DynamicImage,
+    image2: &DynamicImage,
+    mask: &Mask,
+) -> ErrorMetrics {
+    error_metrics(image1, image2, Some(mask))
+}
+
+fn error_metrics(
+    image1: &DynamicImage,
+    image2: &DynamicImage,
+    mask: Option<&Mask>,
+) -> ErrorMetrics {
+    let samples1 = Samples::of(image1);
+    let samples2 = Samples::of(image2);
+    let max_val = max_channel_value(image1, image2);
+    let pixel_count = match mask {
+        Some(mask) => mask.included_count(),
+        None => u64::from(image1.width()) * u64::from(image1.height()),
+    };
+    let channels = usize::from(image1.color().channel_count());
+
+    // Squared differences of integer samples sum exactly in an f64. Rows are
+    // summed first, then the row sums in order, like `calculate_diff_ratio`.
+    let rows = par::map_ranges(samples1.len(), par::row_len(image1), |range| {
+        let start = range.start;
+        let mut sums = vec![0.0; channels];
+        samples1
+            .slice(range.clone())
+            .for_each_pair(&samples2.slice(range), |i, p1, p2| {
+                if mask.is_some_and(|mask| mask.is_ignored((start + i) / channels)) {
+                    return;
+                }
+                let d = p1 - p2;
+                sums[i % channels] += d * d;
+            });
+        sums
+    });
+    let mut sums = vec![0.0; channels];
+    for row in rows {
+        for (sum, row_sum) in sums.iter_mut().zip(row) {
+            *sum += row_sum;
+        }
+    }
+
+    // With nothing to compare, nothing differs
+    let average = |sum: f64, count: u64| if count == 0 { 0.0 } else { sum / count as f64 };
+    let channel_mse: Vec<f64> = sums.iter().map(|&sum| average(sum, pixel_count)).collect();
+    let mse = average(sums.iter().sum(), pixel_count * channels as u64);
+
+    ErrorMetrics {
+        channel_rmse: channel_mse.iter().map(|m| m.sqrt()).collect(),
+        channel_psnr: channel_mse.iter().map(|&m| psnr(m, max_val)).collect(),
+        channel_mse,
+        mse,
+        rmse: mse.sqrt(),
+        psnr: psnr(mse, max_val),
+    }
+}
+
+#[test]
+fn test_psnr() {
+    assert_eq!(psnr(0.0, 255.0), f64::INFINITY);
+    assert!((psnr(255.0 * 255.0, 255.0) - 0.0).abs() < 1e-12);
+    assert!((psnr(255.0 * 255.0 / 100.0, 255.0) - 20.0).abs() < 1e-12);
+}
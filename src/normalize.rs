@@ -0,0 +1,252 @@
+//! Bringing two images into a form where they can be compared.
+
+use std::fmt;
+
+use image::imageops::{self, FilterType};
+use image::{ColorType, DynamicImage, GenericImageView, ImageBuffer, Pixel, Rgba};
+
+use crate::{DiffError, Mask, Rect};
+
+/// The color type `--normalize-color` converts to when none is given
+pub const DEFAULT_NORMALIZED_COLOR: ColorType = ColorType::Rgba8;
+
+/// Parse a color type name as used on the command line, e.g. `rgba8` or `l16`
+pub fn parse_color_type(s: &str) -> Result<ColorType, String> {
+    match s {
+        "l8" => Ok(ColorType::L8),
+        "la8" => Ok(ColorType::La8),
+        "rgb8" => Ok(ColorType::Rgb8),
+        "rgba8" => Ok(ColorType::Rgba8),
+        "l16" => Ok(ColorType::L16),
+        "la16" => Ok(ColorType::La16),
+        "rgb16" => Ok(ColorType::Rgb16),
+        "rgba16" => Ok(ColorType::Rgba16),
+        "rgb32f" => Ok(ColorType::Rgb32F),
+        "rgba32f" => Ok(ColorType::Rgba32F),
+        _ => Err(format!("unknown color type \"{}\"", s)),
+    }
+}
+
+/// The name of a color type as used on the command line, see `parse_color_type`
+pub(crate) fn color_type_name(color: ColorType) -> &'static str {
+    match color {
+        ColorType::L8 => "l8",
+        ColorType::La8 => "la8",
+        ColorType::Rgb8 => "rgb8",
+        ColorType::Rgba8 => "rgba8",
+        ColorType::L16 => "l16",
+        ColorType::La16 => "la16",
+        ColorType::Rgb16 => "rgb16",
+        ColorType::Rgba16 => "rgba16",
+        ColorType::Rgb32F => "rgb32f",
+        ColorType::Rgba32F => "rgba32f",
+        _ => "unknown",
+    }
+}
+
+/// Convert an image to the given color type.
+///
+/// An image without an alpha channel is treated as fully opaque, so it gains an
+/// alpha of 255 (or 65535, or 1.0). Converting to a type without alpha drops the
+/// alpha channel as is, without compositing onto a background.
+pub fn normalize_color(image: &DynamicImage, color: ColorType) -> DynamicImage {
+    if image.color() == color {
+        return image.clone();
+    }
+    match color {
+        ColorType::L8 => DynamicImage::ImageLuma8(image.to_luma8()),
+        ColorType::La8 => DynamicImage::ImageLumaA8(image.to_luma_alpha8()),
+        ColorType::Rgb8 => DynamicImage::ImageRgb8(image.to_rgb8()),
+        ColorType::L16 => DynamicImage::ImageLuma16(image.to_luma16()),
+        ColorType::La16 => DynamicImage::ImageLumaA16(image.to_luma_alpha16()),
+        ColorType::Rgb16 => DynamicImage::ImageRgb16(image.to_rgb16()),
+        ColorType::Rgba16 => DynamicImage::ImageRgba16(image.to_rgba16()),
+        ColorType::Rgb32F => DynamicImage::ImageRgb32F(image.to_rgb32f()),
+        ColorType::Rgba32F => DynamicImage::ImageRgba32F(image.to_rgba32f()),
+        _ => DynamicImage::ImageRgba8(image.to_rgba8()),
+    }
+}
+
+/// Parse a color given as `r,g,b`, `r,g,b,a`, `#rrggbb` or `#rrggbbaa`
+pub fn parse_color(s: &str) -> Result<Rgba<u8>, String> {
+    let invalid = || format!("\"{}\" is not a color like 255,0,255 or #ff00ff", s);
+    let channels: Vec<u8> = if let Some(hex) = s.strip_prefix('#') {
+        if hex.len() != 6 && hex.len() != 8 {
+            return Err(invalid());
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| {
+                hex.get(i..i + 2)
+                    .and_then(|c| u8::from_str_radix(c, 16).ok())
+            })
+            .collect::<Option<_>>()
+            .ok_or_else(invalid)?
+    } else {
+        s.split(',')
+            .map(|c| c.trim().parse().ok())
+            .collect::<Option<_>>()
+            .ok_or_else(invalid)?
+    };
+    match channels[..] {
+        [r, g, b] => Ok(Rgba([r, g, b, 255])),
+        [r, g, b, a] => Ok(Rgba([r, g, b, a])),
+        _ => Err(invalid()),
+    }
+}
+
+/// Parse a resize filter name as used on the command line
+pub fn parse_filter(s: &str) -> Result<FilterType, String> {
+    match s {
+        "nearest" => Ok(FilterType::Nearest),
+        "triangle" => Ok(FilterType::Triangle),
+        "catmullrom" => Ok(FilterType::CatmullRom),
+        "gaussian" => Ok(FilterType::Gaussian),
+        "lanczos3" => Ok(FilterType::Lanczos3),
+        _ => Err(format!("unknown filter \"{}\"", s)),
+    }
+}
+
+/// What to do when the two images have different dimensions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SizeMismatch {
+    /// Fail with `DiffError::DimensionMismatch`
+    #[default]
+    Error,
+    /// Crop both images to the area they have in common, anchored top-left
+    Crop,
+    /// Pad the smaller extent of each image with the given color, anchored
+    /// top-left. Pixels that are padding in either image always count as
+    /// differing in the ratio and pixel counts, whatever their color.
+    Pad(Rgba<u8>),
+    /// Resize the second image to the dimensions of the first
+    Resize(FilterType),
+}
+
+impl fmt::Display for SizeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SizeMismatch::Error => write!(f, "error"),
+            SizeMismatch::Crop => write!(f, "cropped to the common area"),
+            SizeMismatch::Pad(Rgba([r, g, b, a])) => {
+                write!(f, "padded with color {},{},{},{}", r, g, b, a)
+            }
+            SizeMismatch::Resize(filter) => {
+                write!(f, "resized second image with {:?} filter", filter)
+            }
+        }
+    }
+}
+
+impl SizeMismatch {
+    /// Bring two images to the same dimensions. Returns `None` when they already
+    /// match, so there was nothing to do.
+    pub fn apply(
+        self,
+        image1: &DynamicImage,
+        image2: &DynamicImage,
+    ) -> Result<Option<(DynamicImage, DynamicImage)>, DiffError> {
+        let (w1, h1) = image1.dimensions();
+        let (w2, h2) = image2.dimensions();
+        if (w1, h1) == (w2, h2) {
+            return Ok(None);
+        }
+
+        let resized = match self {
+            SizeMismatch::Error => {
+                return Err(DiffError::DimensionMismatch {
+                    image1: (w1, h1),
+                    image2: (w2, h2),
+                })
+            }
+            SizeMismatch::Crop => {
+                let (w, h) = (w1.min(w2), h1.min(h2));
+                (image1.crop_imm(0, 0, w, h), image2.crop_imm(0, 0, w, h))
+            }
+            SizeMismatch::Pad(color) => {
+                let (w, h) = (w1.max(w2), h1.max(h2));
+                (pad(image1, w, h, color), pad(image2, w, h, color))
+            }
+            SizeMismatch::Resize(filter) => (image1.clone(), image2.resize_exact(w1, h1, filter)),
+        };
+        Ok(Some(resized))
+    }
+
+    /// The pixels `apply` adds as padding to either image, as a `Mask` flagging
+    /// them, or `None` when nothing is padded
+    pub fn padding(self, (w1, h1): (u32, u32), (w2, h2): (u32, u32)) -> Option<Mask> {
+        if !matches!(self, SizeMismatch::Pad(_)) || (w1, h1) == (w2, h2) {
+            return None;
+        }
+        let (width, height) = (w1.max(w2), h1.max(h2));
+        let mut padding = Mask::new(width, height);
+        for &(w, h) in &[(w1, h1), (w2, h2)] {
+            padding.ignore(Rect {
+                x: w,
+                y: 0,
+                width: width - w,
+                height,
+            });
+            padding.ignore(Rect {
+                x: 0,
+                y: h,
+                width,
+                height: height - h,
+            });
+        }
+        Some(padding)
+    }
+}
+
+/// Place `buffer` at the top-left of a `width` x `height` canvas filled with the
+/// first pixel of `fill`
+fn pad_buffer<P: Pixel>(
+    buffer: &ImageBuffer<P, Vec<P::Subpixel>>,
+    fill: &ImageBuffer<P, Vec<P::Subpixel>>,
+    (width, height): (u32, u32),
+) -> ImageBuffer<P, Vec<P::Subpixel>> {
+    let mut canvas = ImageBuffer::from_pixel(width, height, fill[(0, 0)]);
+    imageops::replace(&mut canvas, buffer, 0, 0);
+    canvas
+}
+
+/// A 1x1 image of the given color in the given color type, letting the image
+/// crate do the conversion
+pub(crate) fn color_pixel(color: Rgba<u8>, color_type: ColorType) -> DynamicImage {
+    normalize_color(
+        &DynamicImage::ImageRgba8(ImageBuffer::from_pixel(1, 1, color)),
+        color_type,
+    )
+}
+
+/// Pad an image to `width` x `height` with `color`, keeping its color type
+fn pad(image: &DynamicImage, width: u32, height: u32, color: Rgba<u8>) -> DynamicImage {
+    use DynamicImage::*;
+
+    let fill = color_pixel(color, image.color());
+    let size = (width, height);
+    match (image, &fill) {
+        (ImageLuma8(b), ImageLuma8(f)) => ImageLuma8(pad_buffer(b, f, size)),
+        (ImageLumaA8(b), ImageLumaA8(f)) => ImageLumaA8(pad_buffer(b, f, size)),
+        (ImageRgb8(b), ImageRgb8(f)) => ImageRgb8(pad_buffer(b, f, size)),
+        (ImageRgba8(b), ImageRgba8(f)) => ImageRgba8(pad_buffer(b, f, size)),
+        (ImageLuma16(b), ImageLuma16(f)) => ImageLuma16(pad_buffer(b, f, size)),
+        (ImageLumaA16(b), ImageLumaA16(f)) => ImageLumaA16(pad_buffer(b, f, size)),
+        (ImageRgb16(b), ImageRgb16(f)) => ImageRgb16(pad_buffer(b, f, size)),
+        (ImageRgba16(b), ImageRgba16(f)) => ImageRgba16(pad_buffer(b, f, size)),
+        (ImageRgb32F(b), ImageRgb32F(f)) => ImageRgb32F(pad_buffer(b, f, size)),
+        (ImageRgba32F(b), ImageRgba32F(f)) => ImageRgba32F(pad_buffer(b, f, size)),
+        _ => unreachable!("unknown color type {:?}", image.color()),
+    }
+}
+
+#[test]
+fn test_parse_color() {
+    assert_eq!(parse_color("255,0,255"), Ok(Rgba([255, 0, 255, 255])));
+    assert_eq!(parse_color("1, 2, 3, 4"), Ok(Rgba([1, 2, 3, 4])));
+    assert_eq!(parse_color("#ff00ff"), Ok(Rgba([255, 0, 255, 255])));
+    assert_eq!(parse_color("#ff00ff80"), Ok(Rgba([255, 0, 255, 128])));
+    assert!(parse_color("#ff00f").is_err());
+    assert!(parse_color("1,2").is_err());
+    assert!(parse_color("256,0,0").is_err());
+}
@@ -0,0 +1,76 @@
+//! Splitting the per-pixel loops across threads when the `parallel` feature is
+//! enabled.
+//!
+//! Work is split into the same chunks with or without the feature, and the
+//! results of the chunks are combined in order, so floating-point sums come out
+//! bit-identical either way. On a single thread (without the feature, or in a
+//! rayon pool of one thread such as with `RAYON_NUM_THREADS=1`) the chunks are
+//! processed in a plain loop.
+
+use std::ops::Range;
+
+use image::DynamicImage;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// The number of channel values in a row of the image, the unit most per-pixel
+/// loops are split into
+pub(crate) fn row_len(image: &DynamicImage) -> usize {
+    image.width() as usize * usize::from(image.color().channel_count())
+}
+
+/// Whether there is more than one thread to split work across
+#[cfg(feature = "parallel")]
+fn threaded() -> bool {
+    rayon::current_num_threads() > 1
+}
+
+/// Split `0..len` into ranges of `chunk` indices (the last one may be shorter),
+/// apply `f` to each and return the results in order
+pub(crate) fn map_ranges<R: Send>(
+    len: usize,
+    chunk: usize,
+    f: impl Fn(Range<usize>) -> R + Sync + Send,
+) -> Vec<R> {
+    let chunk = chunk.max(1);
+    let range = |i: usize| i * chunk..((i + 1) * chunk).min(len);
+    let count = len.div_ceil(chunk);
+    #[cfg(feature = "parallel")]
+    if threaded() {
+        return (0..count).into_par_iter().map(|i| f(range(i))).collect();
+    }
+    (0..count).map(|i| f(range(i))).collect()
+}
+
+/// Apply `f` to every pair of values of two slices of the same length
+pub(crate) fn zip_map<T: Copy + Sync, R: Send>(
+    values1: &[T],
+    values2: &[T],
+    f: impl Fn(T, T) -> R + Sync + Send,
+) -> Vec<R> {
+    #[cfg(feature = "parallel")]
+    if threaded() {
+        return values1
+            .par_iter()
+            .zip(values2.par_iter())
+            .map(|(&a, &b)| f(a, b))
+            .collect();
+    }
+    values1
+        .iter()
+        .zip(values2.iter())
+        .map(|(&a, &b)| f(a, b))
+        .collect()
+}
+
+#[test]
+fn test_map_ranges() {
+    assert_eq!(map_ranges(10, 4, |r| r), vec![0..4, 4..8, 8..10]);
+    assert_eq!(map_ranges(0, 4, |r| r), vec![]);
+    // A chunk of 0, as for an image without width, still makes progress
+    assert_eq!(map_ranges(2, 0, |r| r.len()), vec![1, 1]);
+    assert_eq!(
+        zip_map(&[1, 5], &[3, 2], |a: u8, b| a.abs_diff(b)),
+        vec![2, 3]
+    );
+}
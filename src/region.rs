@@ -0,0 +1,102 @@
+//! Comparing only selected regions of the images, each reported on its own.
+
+use std::fs;
+use std::path::Path;
+
+use image::{DynamicImage, GenericImageView};
+use serde::Serialize;
+
+use crate::{DiffError, Rect};
+
+/// A named rectangle to compare separately from the rest of the image
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Region {
+    pub name: String,
+    pub rect: Rect,
+}
+
+impl Region {
+    /// A region named after its coordinates, as given on the command line
+    pub fn unnamed(rect: Rect) -> Region {
+        Region {
+            name: rect.to_string(),
+            rect,
+        }
+    }
+
+    /// The part of the region that lies within `image`
+    pub fn clip(&self, image: &DynamicImage) -> Result<Rect, DiffError> {
+        let (width, height) = image.dimensions();
+        let x_end = self.rect.x.saturating_add(self.rect.width).min(width);
+        let y_end = self.rect.y.saturating_add(self.rect.height).min(height);
+        if self.rect.x >= x_end || self.rect.y >= y_end {
+            return Err(DiffError::RegionOutside {
+                name: self.name.clone(),
+                image: (width, height),
+            });
+        }
+        Ok(Rect {
+            x: self.rect.x,
+            y: self.rect.y,
+            width: x_end - self.rect.x,
+            height: y_end - self.rect.y,
+        })
+    }
+}
+
+/// The part of an image within `rect`, which must lie inside the image
+pub fn crop(image: &DynamicImage, rect: Rect) -> DynamicImage {
+    image.crop_imm(rect.x, rect.y, rect.width, rect.height)
+}
+
+/// Parse regions from text with one `name x,y,width,height` per line. Blank lines
+/// and lines starting with `#` are skipped.
+pub fn parse_regions(text: &str) -> Result<Vec<Region>, String> {
+    let mut regions = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields[..] {
+            [name, rect] => regions.push(Region {
+                name: name.to_string(),
+                rect: rect
+                    .parse()
+                    .map_err(|e| format!("line {}: {}", number + 1, e))?,
+            }),
+            _ => {
+                return Err(format!(
+                    "line {}: expected \"name x,y,width,height\"",
+                    number + 1
+                ))
+            }
+        }
+    }
+    Ok(regions)
+}
+
+/// Read regions from a file in the format of `parse_regions`
+pub fn load_regions(path: &str) -> Result<Vec<Region>, DiffError> {
+    if !Path::new(path).exists() {
+        return Err(DiffError::MissingFile(path.to_string()));
+    }
+    let invalid = |message| DiffError::RegionFile {
+        path: path.to_string(),
+        message,
+    };
+    let text = fs::read_to_string(path).map_err(|e| invalid(e.to_string()))?;
+    parse_regions(&text).map_err(invalid)
+}
+
+#[test]
+fn test_parse_regions() {
+    let text = "# widgets\nheader 0,0,800,60\n\n  avatar 700,10,40,40\n";
+    let regions = parse_regions(text).unwrap();
+    assert_eq!(regions.len(), 2);
+    assert_eq!(regions[1].name, "avatar");
+    assert_eq!(regions[1].rect, "700,10,40,40".parse().unwrap());
+    assert!(parse_regions("header\n").is_err());
+    assert!(parse_regions("header 0,0,800\n").is_err());
+}
@@ -0,0 +1,319 @@
+//! The results of a comparison, printed as text for people or as JSON for other
+//! programs.
+
+use std::str::FromStr;
+
+use image::{DynamicImage, GenericImageView};
+use serde::{Serialize, Serializer};
+
+use crate::normalize::color_type_name;
+use crate::{ChangeBox, ChannelStats, DiffError, Metric, PixelCount, Region, Verdict};
+
+/// How the results of a comparison are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// One finding per line, see `Report::print_text`
+    #[default]
+    Text,
+    /// The whole `Report` as one JSON object
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OutputFormat, String> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("unknown output format \"{}\"", s)),
+        }
+    }
+}
+
+/// An input image as it was loaded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ImageInfo {
+    pub path: String,
+    /// `None` when the image could not be loaded
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// The color type, named as for `--normalize-color`
+    pub color: Option<&'static str>,
+}
+
+impl ImageInfo {
+    pub(crate) fn of(path: &str, image: &DynamicImage) -> ImageInfo {
+        let (width, height) = image.dimensions();
+        ImageInfo {
+            path: path.to_string(),
+            width: Some(width),
+            height: Some(height),
+            color: Some(color_type_name(image.color())),
+        }
+    }
+
+    fn unknown(path: &str) -> ImageInfo {
+        ImageInfo {
+            path: path.to_string(),
+            width: None,
+            height: None,
+            color: None,
+        }
+    }
+}
+
+/// The metric and pixel count of the images, or of one region of them
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Measurement {
+    /// The region measured, or `None` for the whole image
+    pub region: Option<Region>,
+    /// The value of the configured metric. PSNR of identical images is infinite,
+    /// which JSON shows as the string `"inf"`.
+    #[serde(serialize_with = "serialize_number")]
+    pub value: f64,
+    /// The value of each channel, for MSE, RMSE and PSNR
+    #[serde(serialize_with = "serialize_numbers")]
+    pub channels: Option<Vec<f64>>,
+    /// The differing pixels, when they were counted
+    pub pixels: Option<PixelCount>,
+    /// With a ΔE metric, the ΔE above which `pixels` counts a pixel as differing
+    #[serde(serialize_with = "serialize_optional_number")]
+    pub delta_e_tolerance: Option<f64>,
+    pub verdict: Verdict,
+}
+
+/// A number that JSON can't hold as one, written as `"inf"`, `"-inf"` or `"nan"`
+/// so that it can't be mistaken for a missing value. Every `f64` in the JSON
+/// output is written through one of the `serialize_*number*` functions below.
+struct Number(f64);
+
+impl Serialize for Number {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            v if v.is_finite() => serializer.serialize_f64(v),
+            v if v.is_nan() => serializer.serialize_str("nan"),
+            v if v > 0.0 => serializer.serialize_str("inf"),
+            _ => serializer.serialize_str("-inf"),
+        }
+    }
+}
+
+pub(crate) fn serialize_number<S: Serializer>(
+    value: &f64,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    Number(*value).serialize(serializer)
+}
+
+fn serialize_optional_number<S: Serializer>(
+    value: &Option<f64>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    value.map(Number).serialize(serializer)
+}
+
+fn serialize_numbers<S: Serializer>(
+    values: &Option<Vec<f64>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    values
+        .as_ref()
+        .map(|values| values.iter().map(|&v| Number(v)).collect::<Vec<_>>())
+        .serialize(serializer)
+}
+
+/// The files written during a comparison
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Outputs {
+    pub diff: Option<String>,
+    pub blink: Option<String>,
+    pub onion_skin: Option<String>,
+    pub boxes: Option<String>,
+    /// The HTML report
+    pub report: Option<String>,
+}
+
+/// Why a comparison failed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ErrorInfo {
+    /// The kind of error, see `DiffError::kind`
+    pub kind: &'static str,
+    pub message: String,
+}
+
+/// Everything a comparison found, see `compare`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    pub image1: ImageInfo,
+    pub image2: ImageInfo,
+    /// The dimensions the images were compared at, after any size mismatch was
+    /// handled
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// How images of different sizes were brought to the same size
+    pub size_mismatch: Option<String>,
+    pub metric: Metric,
+    /// Pixels left out by ignore rectangles and the mask
+    pub ignored_pixels: Option<u64>,
+    /// Differing pixels treated as equal because they look anti-aliased
+    pub antialiased_pixels: Option<u64>,
+    /// One for the whole image or one per region; empty when only the diff image
+    /// was asked for
+    pub measurements: Vec<Measurement>,
+    pub stats: Option<Vec<ChannelStats>>,
+    pub boxes: Option<Vec<ChangeBox>>,
+    pub outputs: Outputs,
+    /// `None` when the comparison failed
+    pub verdict: Option<Verdict>,
+    /// `None` unless the comparison failed
+    pub error: Option<ErrorInfo>,
+}
+
+impl Report {
+    /// A report of the given images with nothing found yet
+    pub(crate) fn new(image1: ImageInfo, image2: ImageInfo, metric: Metric) -> Report {
+        Report {
+            image1,
+            image2,
+            width: None,
+            height: None,
+            size_mismatch: None,
+            metric,
+            ignored_pixels: None,
+            antialiased_pixels: None,
+            measurements: Vec::new(),
+            stats: None,
+            boxes: None,
+            outputs: Outputs::default(),
+            verdict: None,
+            error: None,
+        }
+    }
+
+    /// The report of a comparison of the given paths that failed with `error`
+    pub fn failed(image1: &str, image2: &str, metric: Metric, error: &DiffError) -> Report {
+        let mut report = Report::new(
+            ImageInfo::unknown(image1),
+            ImageInfo::unknown(image2),
+            metric,
+        );
+        report.error = Some(ErrorInfo {
+            kind: error.kind(),
+            message: error.to_string(),
+        });
+        report
+    }
+
+    /// The report as a JSON object
+    pub fn to_json(&self) -> String {
+        // unwrap() is safe: every field serializes, and all map keys are strings
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// Print the findings one per line, in the order they were made
+    pub fn print_text(&self) {
+        let dimensions = |image: &ImageInfo| {
+            format!("{}x{}", image.width.unwrap_or(0), image.height.unwrap_or(0))
+        };
+        let (width, height) = (self.width.unwrap_or(0), self.height.unwrap_or(0));
+        if let Some(ref size_mismatch) = self.size_mismatch {
+            println!(
+                "Size mismatch ({} vs {}): {}, compared at {}x{}",
+                dimensions(&self.image1),
+                dimensions(&self.image2),
+                size_mismatch,
+                width,
+                height
+            );
+        }
+        if let Some(ignored) = self.ignored_pixels {
+            println!(
+                "Ignoring {} of {} pixels",
+                ignored,
+                u64::from(width) * u64::from(height)
+            );
+        }
+        if let Some(antialiased) = self.antialiased_pixels {
+            println!("Ignoring {} anti-aliased pixels", antialiased);
+        }
+        if let Some(ref path) = self.outputs.diff {
+            println!("Wrote diff image to {}", path);
+        }
+        if let Some(ref path) = self.outputs.blink {
+            println!("Wrote blink animation to {}", path);
+        }
+        if let Some(ref path) = self.outputs.onion_skin {
+            println!("Wrote onion skin to {}", path);
+        }
+
+        for channel in self.stats.iter().flatten() {
+            println!(
+                "{}: mean {}, max {}, std dev {}",
+                channel.name, channel.mean, channel.max, channel.std_dev
+            );
+            // Only the bins that hold any pixels, as "bin:count"
+            let bins: Vec<String> = channel
+                .histogram
+                .iter()
+                .enumerate()
+                .filter(|&(_, &count)| count > 0)
+                .map(|(bin, count)| format!("{}:{}", bin, count))
+                .collect();
+            println!("{} histogram: {}", channel.name, bins.join(" "));
+        }
+
+        if let Some(ref boxes) = self.boxes {
+            println!("{} changed areas", boxes.len());
+            for b in boxes {
+                println!(
+                    "{}: {} pixels, mean intensity {}",
+                    b.rect, b.pixels, b.mean_intensity
+                );
+            }
+        }
+        if let Some(ref path) = self.outputs.boxes {
+            println!("Wrote bounding boxes to {}", path);
+        }
+        if let Some(ref path) = self.outputs.report {
+            println!("Wrote HTML report to {}", path);
+        }
+
+        for measurement in &self.measurements {
+            let prefix = match measurement.region {
+                Some(ref region) => format!("{}: ", region.name),
+                None => String::new(),
+            };
+            println!("{}{}", prefix, measurement.value);
+            if let Some(ref channels) = measurement.channels {
+                let channels: Vec<String> = channels.iter().map(|v| v.to_string()).collect();
+                println!("{}channels: {}", prefix, channels.join(" "));
+            }
+            match (measurement.pixels, measurement.delta_e_tolerance) {
+                (Some(count), Some(tolerance)) => println!(
+                    "{}{} pixels differ by more than ΔE {}",
+                    prefix, count.differing, tolerance
+                ),
+                (Some(count), None) => println!(
+                    "{}{} pixels differ ({}%)",
+                    prefix,
+                    count.differing,
+                    count.percentage()
+                ),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[test]
+fn test_failed_report_json() {
+    let error = DiffError::MissingFile("a.png".to_string());
+    let report = Report::failed("a.png", "b.png", Metric::Ratio, &error);
+    let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+    assert_eq!(json["image1"]["path"], "a.png");
+    assert_eq!(json["image2"]["width"], serde_json::Value::Null);
+    assert_eq!(json["metric"], "ratio");
+    assert_eq!(json["verdict"], serde_json::Value::Null);
+    assert_eq!(json["error"]["kind"], "missing_file");
+}
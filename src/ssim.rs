@@ -0,0 +1,273 @@
+//! Structural similarity (SSIM) and its multi-scale variant (MS-SSIM).
+//!
+//! Both are computed on the luma channel using an 11x11 gaussian window with
+//! sigma 1.5, following Wang et al. Near the borders the window is truncated
+//! and renormalised instead of padding the image. 16-bit and floating-point
+//! images are compared at full precision on the same 0-255 scale.
+
+use image::{DynamicImage, GrayImage, Luma};
+
+use crate::depth::{float_max, is_8bit};
+use crate::{par, Mask};
+
+const WINDOW_SIZE: usize = 11;
+const WINDOW_SIGMA: f64 = 1.5;
+const K1: f64 = 0.01;
+const K2: f64 = 0.03;
+const MAX_VAL: f64 = 255.0;
+
+/// Per-scale exponents from the original MS-SSIM paper
+const MS_SSIM_WEIGHTS: [f64; 5] = [0.0448, 0.2856, 0.3001, 0.2363, 0.1333];
+
+/// Per-pixel SSIM values for a pair of images, in row-major order
+#[derive(Debug, Clone)]
+pub struct SsimMap {
+    pub width: u32,
+    pub height: u32,
+    pub values: Vec<f64>,
+}
+
+impl SsimMap {
+    /// The mean SSIM over the whole image
+    pub fn mean(&self) -> f64 {
+        mean(&self.values)
+    }
+
+    /// The mean SSIM over the pixels the mask does not ignore, 1 if it ignores
+    /// them all
+    pub fn masked_mean(&self, mask: &Mask) -> f64 {
+        let included = mask.included_count();
+        if included == 0 {
+            return 1.0;
+        }
+        let sum: f64 = self
+            .values
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| !mask.is_ignored(i))
+            .map(|(_, v)| v)
+            .sum();
+        sum / included as f64
+    }
+
+    /// Render the map as a grayscale image where black is identical and white is
+    /// maximally dissimilar, to match the look of the absolute diff image
+    pub fn to_image(&self) -> GrayImage {
+        GrayImage::from_fn(self.width, self.height, |x, y| {
+            let value = self.values[(y * self.width + x) as usize];
+            let dissimilarity = (1.0 - value).clamp(0.0, 1.0);
+            Luma([(dissimilarity * MAX_VAL).round() as u8])
+        })
+    }
+}
+
+/// A single-channel f64 plane
+struct Plane {
+    width: usize,
+    height: usize,
+    data: Vec<f64>,
+}
+
+impl Plane {
+    /// The luma planes of two images on a 0-255 scale
+    fn from_luma(image1: &DynamicImage, image2: &DynamicImage) -> (Plane, Plane) {
+        if is_8bit(image1) {
+            return (Plane::from_luma8(image1), Plane::from_luma8(image2));
+        }
+        let scale = MAX_VAL / float_max(image1, image2);
+        (
+            Plane::from_luma32f(image1, scale),
+            Plane::from_luma32f(image2, scale),
+        )
+    }
+
+    fn from_luma8(image: &DynamicImage) -> Plane {
+        let luma = image.to_luma8();
+        Plane {
+            width: luma.width() as usize,
+            height: luma.height() as usize,
+            data: luma.into_raw().into_iter().map(f64::from).collect(),
+        }
+    }
+
+    fn from_luma32f(image: &DynamicImage, scale: f64) -> Plane {
+        let luma = image.to_luma32f();
+        Plane {
+            width: luma.width() as usize,
+            height: luma.height() as usize,
+            data: luma
+                .into_raw()
+                .into_iter()
+                .map(|v| f64::from(v) * scale)
+                .collect(),
+        }
+    }
+
+    /// Halve the plane in both directions by averaging 2x2 blocks
+    fn downsample(&self) -> Plane {
+        let width = self.width / 2;
+        let height = self.height / 2;
+        let rows = par::map_ranges(width * height, width, |range| {
+            let y = range.start / width;
+            (0..range.len())
+                .map(|x| {
+                    let i = 2 * y * self.width + 2 * x;
+                    let sum = self.data[i]
+                        + self.data[i + 1]
+                        + self.data[i + self.width]
+                        + self.data[i + self.width + 1];
+                    sum / 4.0
+                })
+                .collect::<Vec<_>>()
+        });
+        let data = rows.concat();
+        Plane {
+            width,
+            height,
+            data,
+        }
+    }
+
+    fn map(&self, other: &Plane, f: impl Fn(f64, f64) -> f64 + Sync + Send) -> Vec<f64> {
+        par::zip_map(&self.data, &other.data, f)
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+fn gaussian_kernel() -> Vec<f64> {
+    let center = (WINDOW_SIZE / 2) as f64;
+    let kernel: Vec<f64> = (0..WINDOW_SIZE)
+        .map(|i| {
+            let d = i as f64 - center;
+            (-(d * d) / (2.0 * WINDOW_SIGMA * WINDOW_SIGMA)).exp()
+        })
+        .collect();
+    let sum: f64 = kernel.iter().sum();
+    kernel.into_iter().map(|k| k / sum).collect()
+}
+
+/// Separable gaussian blur; the kernel is truncated and renormalised at the borders
+fn blur(data: &[f64], width: usize, height: usize, kernel: &[f64]) -> Vec<f64> {
+    let radius = kernel.len() / 2;
+    let convolve = |len: usize, pos: usize, sample: &dyn Fn(usize) -> f64| {
+        let start = pos.saturating_sub(radius);
+        let end = (pos + radius + 1).min(len);
+        let mut acc = 0.0;
+        let mut weight = 0.0;
+        for i in start..end {
+            let k = kernel[i + radius - pos];
+            acc += k * sample(i);
+            weight += k;
+        }
+        acc / weight
+    };
+
+    // Every value is computed on its own, so rows can be blurred in any order
+    let horizontal = par::map_ranges(data.len(), width, |range| {
+        let row = &data[range];
+        (0..width)
+            .map(|x| convolve(width, x, &|i| row[i]))
+            .collect::<Vec<_>>()
+    })
+    .concat();
+
+    par::map_ranges(data.len(), width, |range| {
+        let y = range.start / width;
+        (0..width)
+            .map(|x| convolve(height, y, &|i| horizontal[i * width + x]))
+            .collect::<Vec<_>>()
+    })
+    .concat()
+}
+
+/// Return the per-pixel luminance term and contrast-structure term
+fn ssim_components(p1: &Plane, p2: &Plane) -> (Vec<f64>, Vec<f64>) {
+    let (w, h) = (p1.width, p1.height);
+    let kernel = gaussian_kernel();
+    let c1 = (K1 * MAX_VAL).powi(2);
+    let c2 = (K2 * MAX_VAL).powi(2);
+
+    let mu1 = blur(&p1.data, w, h, &kernel);
+    let mu2 = blur(&p2.data, w, h, &kernel);
+    let e11 = blur(&p1.map(p1, |a, b| a * b), w, h, &kernel);
+    let e22 = blur(&p2.map(p2, |a, b| a * b), w, h, &kernel);
+    let e12 = blur(&p1.map(p2, |a, b| a * b), w, h, &kernel);
+
+    let rows = par::map_ranges(w * h, w, |range| {
+        range
+            .map(|i| {
+                let var1 = e11[i] - mu1[i] * mu1[i];
+                let var2 = e22[i] - mu2[i] * mu2[i];
+                let cov = e12[i] - mu1[i] * mu2[i];
+                (
+                    (2.0 * mu1[i] * mu2[i] + c1) / (mu1[i].powi(2) + mu2[i].powi(2) + c1),
+                    (2.0 * cov + c2) / (var1 + var2 + c2),
+                )
+            })
+            .collect::<Vec<_>>()
+    });
+    rows.concat().into_iter().unzip()
+}
+
+/// Return the per-pixel SSIM map of the two images
+pub fn ssim_map(image1: &DynamicImage, image2: &DynamicImage) -> SsimMap {
+    let (p1, p2) = Plane::from_luma(image1, image2);
+    let (luminance, contrast_structure) = ssim_components(&p1, &p2);
+    let values = par::zip_map(&luminance, &contrast_structure, |l, cs| l * cs);
+
+    SsimMap {
+        width: p1.width as u32,
+        height: p1.height as u32,
+        values,
+    }
+}
+
+/// Return the mean SSIM of the two images, 1 meaning identical
+pub fn calculate_ssim(image1: &DynamicImage, image2: &DynamicImage) -> f64 {
+    ssim_map(image1, image2).mean()
+}
+
+/// Return the MS-SSIM of the two images, 1 meaning identical.
+///
+/// Small images get fewer than the usual five scales (a scale is only used while
+/// both sides are at least as large as the window); the weights of the scales
+/// that are used are renormalised to sum to 1.
+pub fn calculate_ms_ssim(image1: &DynamicImage, image2: &DynamicImage) -> f64 {
+    let (mut p1, mut p2) = Plane::from_luma(image1, image2);
+
+    let mut scales = 1;
+    let (mut w, mut h) = (p1.width / 2, p1.height / 2);
+    while scales < MS_SSIM_WEIGHTS.len() && w.min(h) >= WINDOW_SIZE {
+        scales += 1;
+        w /= 2;
+        h /= 2;
+    }
+    let weights = &MS_SSIM_WEIGHTS[..scales];
+    let weight_sum: f64 = weights.iter().sum();
+
+    let mut result = 1.0;
+    for (scale, weight) in weights.iter().enumerate() {
+        let (luminance, contrast_structure) = ssim_components(&p1, &p2);
+        // Negative contrast-structure values would make the fractional power NaN
+        let mut value = mean(&contrast_structure).max(0.0);
+        if scale == scales - 1 {
+            value *= mean(&luminance);
+        } else {
+            p1 = p1.downsample();
+            p2 = p2.downsample();
+        }
+        result *= value.powf(weight / weight_sum);
+    }
+    result
+}
+
+#[test]
+fn test_gaussian_kernel() {
+    let kernel = gaussian_kernel();
+    assert_eq!(kernel.len(), WINDOW_SIZE);
+    assert!((kernel.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+    assert_eq!(kernel[0], kernel[WINDOW_SIZE - 1]);
+}
@@ -0,0 +1,153 @@
+//! Per-channel statistics of the differences between two images, for finding out
+//! which channel a color pipeline change affected.
+
+use image::{ColorType, DynamicImage};
+use serde::Serialize;
+
+use crate::depth::{max_channel_value, Samples};
+use crate::par;
+use crate::report::serialize_number;
+
+/// How many rows of the images one thread works on at a time. Each chunk has its
+/// own histograms, so chunks are larger than a row.
+const CHUNK_ROWS: usize = 64;
+
+/// The differences of one chunk of rows
+struct Partial {
+    sums: Vec<f64>,
+    squares: Vec<f64>,
+    maxima: Vec<f64>,
+    histograms: Vec<Vec<u64>>,
+}
+
+impl Partial {
+    fn new(channels: usize) -> Partial {
+        Partial {
+            sums: vec![0.0; channels],
+            squares: vec![0.0; channels],
+            maxima: vec![0.0; channels],
+            histograms: vec![vec![0; HISTOGRAM_BINS]; channels],
+        }
+    }
+
+    /// Add the differences of the next chunk
+    fn add(&mut self, other: Partial) {
+        for c in 0..self.sums.len() {
+            self.sums[c] += other.sums[c];
+            self.squares[c] += other.squares[c];
+            self.maxima[c] = self.maxima[c].max(other.maxima[c]);
+            for (count, other) in self.histograms[c].iter_mut().zip(&other.histograms[c]) {
+                *count += other;
+            }
+        }
+    }
+}
+
+/// How many bins a difference histogram has; for 8-bit images, one per value
+pub const HISTOGRAM_BINS: usize = 256;
+
+/// Statistics of the absolute differences of one channel, in units of the
+/// images' native depth like `ErrorMetrics`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChannelStats {
+    /// The channel's name: `R`, `G`, `B`, `A`, or `L` for gray
+    pub name: &'static str,
+    #[serde(serialize_with = "serialize_number")]
+    pub mean: f64,
+    #[serde(serialize_with = "serialize_number")]
+    pub max: f64,
+    #[serde(serialize_with = "serialize_number")]
+    pub std_dev: f64,
+    /// How many pixels fall in each of `HISTOGRAM_BINS` equal bins from no
+    /// difference to the largest channel value
+    pub histogram: Vec<u64>,
+}
+
+/// The names of the channels of a color type, in order
+fn channel_names(color: ColorType) -> &'static [&'static str] {
+    match color.channel_count() {
+        1 => &["L"],
+        2 => &["L", "A"],
+        3 => &["R", "G", "B"],
+        _ => &["R", "G", "B", "A"],
+    }
+}
+
+/// Return statistics of the differences of each channel of two images, in the
+/// images' channel order
+pub fn calculate_channel_stats(image1: &DynamicImage, image2: &DynamicImage) -> Vec<ChannelStats> {
+    let names = channel_names(image1.color());
+    let channels = names.len();
+    let max_val = max_channel_value(image1, image2);
+    let pixel_count = image1.width() as f64 * image1.height() as f64;
+
+    let (samples1, samples2) = (Samples::of(image1), Samples::of(image2));
+    let chunk = par::row_len(image1) * CHUNK_ROWS;
+    let partials = par::map_ranges(samples1.len(), chunk, |range| {
+        let mut partial = Partial::new(channels);
+        samples1
+            .slice(range.clone())
+            .for_each_pair(&samples2.slice(range), |i, p1, p2| {
+                let c = i % channels;
+                let d = (p1 - p2).abs();
+                partial.sums[c] += d;
+                partial.squares[c] += d * d;
+                partial.maxima[c] = partial.maxima[c].max(d);
+                let bin = (d / max_val * (HISTOGRAM_BINS - 1) as f64).round() as usize;
+                partial.histograms[c][bin.min(HISTOGRAM_BINS - 1)] += 1;
+            });
+        partial
+    });
+    let mut total = Partial::new(channels);
+    for partial in partials {
+        total.add(partial);
+    }
+    let Partial {
+        sums,
+        squares,
+        maxima,
+        histograms,
+    } = total;
+
+    names
+        .iter()
+        .zip(histograms)
+        .enumerate()
+        .map(|(c, (&name, histogram))| {
+            let (mean, variance) = if pixel_count == 0.0 {
+                (0.0, 0.0)
+            } else {
+                let mean = sums[c] / pixel_count;
+                (mean, squares[c] / pixel_count - mean * mean)
+            };
+            ChannelStats {
+                name,
+                mean,
+                max: maxima[c],
+                // Rounding can leave the variance of equal differences just below 0
+                std_dev: variance.max(0.0).sqrt(),
+                histogram,
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn test_channel_stats() {
+    let image1 = DynamicImage::ImageRgb8(image::RgbImage::from_raw(2, 1, vec![0; 6]).unwrap());
+    let image2 = DynamicImage::ImageRgb8(
+        image::RgbImage::from_raw(2, 1, vec![10, 0, 255, 30, 0, 255]).unwrap(),
+    );
+    let stats = calculate_channel_stats(&image1, &image2);
+    assert_eq!(stats.len(), 3);
+    assert_eq!(stats[0].name, "R");
+    assert_eq!(
+        (stats[0].mean, stats[0].max, stats[0].std_dev),
+        (20.0, 30.0, 10.0)
+    );
+    assert_eq!(stats[0].histogram[10], 1);
+    assert_eq!(stats[0].histogram[30], 1);
+    assert_eq!(stats[1].histogram[0], 2);
+    assert_eq!((stats[2].mean, stats[2].std_dev), (255.0, 0.0));
+    assert_eq!(stats[2].histogram[255], 2);
+}
@@ -0,0 +1,221 @@
+//! Ways of rendering the difference between two images for a human to look at.
+
+use std::str::FromStr;
+
+use image::{DynamicImage, GenericImageView, Luma, Rgba, RgbaImage};
+
+use crate::changed_pixels;
+use crate::depth::{max_channel_value, Samples};
+use crate::text::{draw_text, text_height, text_width};
+
+/// The color changed pixels are painted in on an overlay unless another is given
+pub const DEFAULT_HIGHLIGHT_COLOR: Rgba<u8> = Rgba([255, 0, 0, 255]);
+
+/// How much of the first image shows through on an overlay, as in pixelmatch
+const OVERLAY_OPACITY: f64 = 0.1;
+
+/// What the diff image looks like
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiffStyle {
+    /// The per-channel absolute difference (or the SSIM or ΔE map)
+    #[default]
+    Absolute,
+    /// A faded grayscale copy of the first image with the changed pixels
+    /// painted in a highlight color
+    Overlay,
+    /// The magnitude of the difference (or the SSIM or ΔE map) mapped through a
+    /// colormap
+    Heatmap,
+}
+
+impl FromStr for DiffStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DiffStyle, String> {
+        match s {
+            "absolute" => Ok(DiffStyle::Absolute),
+            "overlay" => Ok(DiffStyle::Overlay),
+            "heatmap" => Ok(DiffStyle::Heatmap),
+            _ => Err(format!("unknown style \"{}\"", s)),
+        }
+    }
+}
+
+/// Render `image` desaturated and faded towards white, with the pixels flagged in
+/// `changed` (row-major, one per pixel) painted in `highlight`
+pub fn overlay(image: &DynamicImage, changed: &[bool], highlight: Rgba<u8>) -> RgbaImage {
+    let luma = image.to_luma8();
+    let faded =
+        |Luma([l]): Luma<u8>| (255.0 - (255.0 - f64::from(l)) * OVERLAY_OPACITY).round() as u8;
+    RgbaImage::from_fn(luma.width(), luma.height(), |x, y| {
+        if changed[(y * luma.width() + x) as usize] {
+            highlight
+        } else {
+            let v = faded(*luma.get_pixel(x, y));
+            Rgba([v, v, v, 255])
+        }
+    })
+}
+
+/// Render an overlay of the two images, where a pixel has changed if a channel
+/// differs by more than `tolerance` (on the 8-bit scale)
+pub fn overlay_image(
+    image1: &DynamicImage,
+    image2: &DynamicImage,
+    tolerance: u8,
+    highlight: Rgba<u8>,
+) -> RgbaImage {
+    overlay(
+        image1,
+        &changed_pixels(image1, image2, tolerance, None),
+        highlight,
+    )
+}
+
+/// Sampled colormaps, from low to high
+#[rustfmt::skip]
+const VIRIDIS: [[u8; 3]; 9] = [
+    [68, 1, 84], [72, 40, 120], [62, 73, 137], [49, 104, 142], [38, 130, 142],
+    [31, 158, 137], [53, 183, 121], [110, 206, 88], [253, 231, 37],
+];
+#[rustfmt::skip]
+const INFERNO: [[u8; 3]; 9] = [
+    [0, 0, 4], [27, 12, 65], [74, 12, 107], [120, 28, 109], [165, 44, 96],
+    [207, 68, 70], [237, 105, 37], [251, 155, 6], [252, 255, 164],
+];
+
+/// The colors a heatmap uses for low to high values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Colormap {
+    /// Perceptually uniform, dark purple to yellow
+    #[default]
+    Viridis,
+    /// Perceptually uniform, black to pale yellow
+    Inferno,
+    /// Blue to red through cyan and yellow
+    Jet,
+}
+
+impl Colormap {
+    /// The color of a value between 0 and 1; values outside are clamped
+    pub fn color(self, t: f64) -> Rgba<u8> {
+        let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+        let samples = match self {
+            Colormap::Viridis => &VIRIDIS,
+            Colormap::Inferno => &INFERNO,
+            Colormap::Jet => {
+                let c = |offset: f64| {
+                    let v = (1.5 - (4.0 * t - offset).abs()).clamp(0.0, 1.0);
+                    (v * 255.0).round() as u8
+                };
+                return Rgba([c(3.0), c(2.0), c(1.0), 255]);
+            }
+        };
+        let position = t * (samples.len() - 1) as f64;
+        let i = (position.floor() as usize).min(samples.len() - 2);
+        let f = position - i as f64;
+        let c = |channel: usize| {
+            let (a, b) = (
+                f64::from(samples[i][channel]),
+                f64::from(samples[i + 1][channel]),
+            );
+            (a + (b - a) * f).round() as u8
+        };
+        Rgba([c(0), c(1), c(2), 255])
+    }
+}
+
+impl FromStr for Colormap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Colormap, String> {
+        match s {
+            "viridis" => Ok(Colormap::Viridis),
+            "inferno" => Ok(Colormap::Inferno),
+            "jet" => Ok(Colormap::Jet),
+            _ => Err(format!("unknown colormap \"{}\"", s)),
+        }
+    }
+}
+
+/// The magnitude of the difference of every pixel in row-major order: the mean
+/// absolute difference of its channels relative to the largest channel value,
+/// from 0 to 1
+pub fn difference_magnitude(image1: &DynamicImage, image2: &DynamicImage) -> Vec<f64> {
+    let (width, height) = image1.dimensions();
+    let channels = usize::from(image1.color().channel_count());
+    let scale = 1.0 / (max_channel_value(image1, image2) * channels as f64);
+    let mut magnitude = vec![0.0; width as usize * height as usize];
+    Samples::of(image1).for_each_pair(&Samples::of(image2), |i, p1, p2| {
+        magnitude[i / channels] += (p1 - p2).abs() * scale;
+    });
+    magnitude
+}
+
+/// Render per-pixel values (row-major) through a colormap, with `min` and
+/// everything below at the low end and `max` and everything above at the high end
+pub fn heatmap(
+    values: &[f64],
+    width: u32,
+    height: u32,
+    colormap: Colormap,
+    (min, max): (f64, f64),
+) -> RgbaImage {
+    let range = if max > min { max - min } else { 1.0 };
+    RgbaImage::from_fn(width, height, |x, y| {
+        colormap.color((values[(y * width + x) as usize] - min) / range)
+    })
+}
+
+/// Return the heatmap extended downwards by a legend: a bar showing the colormap,
+/// with the values of its ends written below
+pub fn with_legend(heatmap: &RgbaImage, colormap: Colormap, (min, max): (f64, f64)) -> RgbaImage {
+    const MARGIN: u32 = 4;
+    const BAR_HEIGHT: u32 = 10;
+    let (width, height) = heatmap.dimensions();
+    let legend_height = MARGIN + BAR_HEIGHT + MARGIN + text_height(1) + MARGIN;
+
+    let white = Rgba([255, 255, 255, 255]);
+    let mut out = RgbaImage::from_pixel(width, height + legend_height, white);
+    image::imageops::replace(&mut out, heatmap, 0, 0);
+    let (bar_x, bar_width) = if width > 2 * MARGIN {
+        (MARGIN, width - 2 * MARGIN)
+    } else {
+        (0, width)
+    };
+    for x in 0..bar_width {
+        let t = f64::from(x) / f64::from(bar_width.max(2) - 1);
+        for y in 0..BAR_HEIGHT {
+            out.put_pixel(bar_x + x, height + MARGIN + y, colormap.color(t));
+        }
+    }
+
+    let black = Rgba([0, 0, 0, 255]);
+    let text_y = height + MARGIN + BAR_HEIGHT + MARGIN;
+    draw_text(&mut out, MARGIN, text_y, &format!("{}", min), black, 1);
+    let max_label = format!("{}", max);
+    let max_x = width.saturating_sub(MARGIN + text_width(&max_label, 1));
+    draw_text(&mut out, max_x, text_y, &max_label, black, 1);
+    out
+}
+
+#[test]
+fn test_overlay_fades() {
+    let image =
+        DynamicImage::ImageLuma8(image::GrayImage::from_raw(3, 1, vec![0, 255, 0]).unwrap());
+    let out = overlay(&image, &[false, false, true], DEFAULT_HIGHLIGHT_COLOR);
+    assert_eq!(out.get_pixel(0, 0), &Rgba([230, 230, 230, 255]));
+    assert_eq!(out.get_pixel(1, 0), &Rgba([255, 255, 255, 255]));
+    assert_eq!(out.get_pixel(2, 0), &DEFAULT_HIGHLIGHT_COLOR);
+}
+
+#[test]
+fn test_colormaps() {
+    assert_eq!(Colormap::Viridis.color(0.0), Rgba([68, 1, 84, 255]));
+    assert_eq!(Colormap::Viridis.color(1.0), Rgba([253, 231, 37, 255]));
+    assert_eq!(Colormap::Inferno.color(-1.0), Rgba([0, 0, 4, 255]));
+    assert_eq!(Colormap::Viridis.color(0.0625), Rgba([70, 21, 102, 255]));
+    assert_eq!(Colormap::Jet.color(0.0), Rgba([0, 0, 128, 255]));
+    assert_eq!(Colormap::Jet.color(0.5), Rgba([128, 255, 128, 255]));
+    assert_eq!(Colormap::Jet.color(1.0), Rgba([128, 0, 0, 255]));
+}
@@ -1,10 +1,19 @@
 extern crate image;
+#[cfg(feature = "parallel")]
+extern crate rayon;
+extern crate serde_json;
 
 use std::fs;
+use std::io;
+
+use image::imageops::FilterType;
+use image::{AnimationDecoder, DynamicImage, GenericImageView};
 
 static MARIO_NODE: &str = "images/mario-circle-node.png";
 static MARIO_CS: &str = "images/mario-circle-cs.png";
 static MARIO_DIFF: &str = "images/mario-diff.png";
+static BLACK: &str = "images/black.png";
+static WHITE: &str = "images/white.png";
 
 #[cfg(test)]
 mod tests {
@@ -16,7 +25,7 @@ mod tests {
         let image2 = image::open(MARIO_NODE).unwrap();
         assert_eq!(
             0.007319618135968298,
-            diffimg::calculate_diff_ratio(image1, image2)
+            diffimg::calculate_diff_ratio(&image1, &image2)
         )
     }
 
@@ -25,14 +34,1140 @@ mod tests {
         let mario_diff_test = "tests/mario-diff.png";
         let image1 = image::open(MARIO_CS).unwrap();
         let image2 = image::open(MARIO_NODE).unwrap();
-        diffimg::create_diff_image(image1, image2, mario_diff_test);
+        diffimg::create_diff_image(&image1, &image2, mario_diff_test).unwrap();
         assert_eq!(
-            image::open(mario_diff_test).unwrap().raw_pixels(),
-            image::open(MARIO_DIFF).unwrap().raw_pixels()
+            image::open(mario_diff_test).unwrap().as_bytes(),
+            image::open(MARIO_DIFF).unwrap().as_bytes()
         );
-        if let Err(_) = fs::remove_file(mario_diff_test) {
+        if fs::remove_file(mario_diff_test).is_err() {
             panic!("Could not remove test file");
         };
     }
 
+    #[test]
+    fn test_run_missing_file() {
+        let config = diffimg::Config {
+            image1: "images/does-not-exist.png",
+            image2: MARIO_NODE,
+            ..Default::default()
+        };
+        match diffimg::run(config) {
+            Err(diffimg::DiffError::MissingFile(path)) => {
+                assert_eq!(path, "images/does-not-exist.png")
+            }
+            other => panic!("expected MissingFile, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_run_dimension_mismatch() {
+        let config = diffimg::Config {
+            image1: BLACK,
+            image2: MARIO_NODE,
+            ..Default::default()
+        };
+        match diffimg::run(config) {
+            Err(diffimg::DiffError::DimensionMismatch { image1, image2 }) => {
+                assert_eq!(image1, (10, 10));
+                assert_eq!(image2, (381, 480));
+            }
+            other => panic!("expected DimensionMismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_count_diff_pixels() {
+        let black = image::open(BLACK).unwrap();
+        let white = image::open(WHITE).unwrap();
+        assert_eq!(diffimg::count_diff_pixels(&black, &black, 0).differing, 0);
+        let count = diffimg::count_diff_pixels(&black, &white, 0);
+        assert_eq!(count.differing, 100);
+        assert_eq!(count.total, 100);
+        assert_eq!(count.percentage(), 100.0);
+        assert_eq!(diffimg::count_diff_pixels(&black, &white, 255).differing, 0);
+    }
+
+    #[test]
+    fn test_run_threshold() {
+        let config = diffimg::Config {
+            image1: MARIO_CS,
+            image2: MARIO_NODE,
+            threshold: Some(0.01),
+            ..Default::default()
+        };
+        assert_eq!(diffimg::run(config).unwrap(), diffimg::Verdict::Pass);
+
+        let config = diffimg::Config {
+            image1: MARIO_CS,
+            image2: MARIO_NODE,
+            threshold: Some(0.005),
+            ..Default::default()
+        };
+        assert_eq!(diffimg::run(config).unwrap(), diffimg::Verdict::Fail);
+    }
+
+    #[test]
+    fn test_run_max_pixels() {
+        let config = diffimg::Config {
+            image1: BLACK,
+            image2: WHITE,
+            max_pixels: Some(99),
+            ..Default::default()
+        };
+        assert_eq!(diffimg::run(config).unwrap(), diffimg::Verdict::Fail);
+    }
+
+    #[test]
+    fn test_ssim() {
+        let black = image::open(BLACK).unwrap();
+        let white = image::open(WHITE).unwrap();
+        let image1 = image::open(MARIO_CS).unwrap();
+        let image2 = image::open(MARIO_NODE).unwrap();
+        assert!((diffimg::calculate_ssim(&image1, &image1) - 1.0).abs() < 1e-9);
+        let ssim = diffimg::calculate_ssim(&image1, &image2);
+        assert!(ssim > 0.9 && ssim < 1.0, "ssim was {}", ssim);
+        assert!(diffimg::calculate_ssim(&black, &white) < 0.01);
+
+        let map = diffimg::ssim_map(&image1, &image2);
+        assert_eq!((map.width, map.height), (381, 480));
+        assert_eq!(map.values.len(), 381 * 480);
+    }
+
+    #[test]
+    fn test_ms_ssim() {
+        let image1 = image::open(MARIO_CS).unwrap();
+        let image2 = image::open(MARIO_NODE).unwrap();
+        assert!((diffimg::calculate_ms_ssim(&image1, &image1) - 1.0).abs() < 1e-9);
+        let ms_ssim = diffimg::calculate_ms_ssim(&image1, &image2);
+        assert!(ms_ssim > 0.9 && ms_ssim < 1.0, "ms-ssim was {}", ms_ssim);
+    }
+
+    #[test]
+    fn test_run_ssim_threshold() {
+        let config = diffimg::Config {
+            image1: MARIO_CS,
+            image2: MARIO_NODE,
+            metric: diffimg::Metric::Ssim,
+            threshold: Some(0.999),
+            ..Default::default()
+        };
+        assert_eq!(diffimg::run(config).unwrap(), diffimg::Verdict::Fail);
+    }
+
+    #[test]
+    fn test_error_metrics() {
+        let black = image::open(BLACK).unwrap();
+        let white = image::open(WHITE).unwrap();
+        let errors = diffimg::calculate_error_metrics(&black, &white);
+        assert_eq!(errors.channel_mse, vec![255.0 * 255.0; 3]);
+        assert_eq!(errors.mse, 255.0 * 255.0);
+        assert_eq!(errors.rmse, 255.0);
+        assert_eq!(errors.psnr, 0.0);
+
+        let errors = diffimg::calculate_error_metrics(&black, &black);
+        assert_eq!(errors.mse, 0.0);
+        assert_eq!(errors.psnr, f64::INFINITY);
+        assert_eq!(errors.channel_psnr, vec![f64::INFINITY; 3]);
+    }
+
+    #[test]
+    fn test_run_psnr_threshold() {
+        let config = diffimg::Config {
+            image1: MARIO_CS,
+            image2: MARIO_NODE,
+            metric: diffimg::Metric::Psnr,
+            threshold: Some(20.0),
+            ..Default::default()
+        };
+        assert_eq!(diffimg::run(config).unwrap(), diffimg::Verdict::Pass);
+    }
+
+    #[test]
+    fn test_delta_e_map() {
+        let black = image::open(BLACK).unwrap();
+        let white = image::open(WHITE).unwrap();
+        let map = diffimg::delta_e_map(&black, &white, diffimg::DeltaEFormula::Cie76);
+        assert!((map.mean() - 100.0).abs() < 1e-3);
+        assert_eq!(map.count_above(99.0), 100);
+
+        let map = diffimg::delta_e_map(&black, &black, diffimg::DeltaEFormula::Ciede2000);
+        assert_eq!(map.mean(), 0.0);
+        assert_eq!(map.count_above(0.0), 0);
+    }
+
+    #[test]
+    fn test_run_delta_e_max_pixels() {
+        let config = diffimg::Config {
+            image1: MARIO_CS,
+            image2: MARIO_NODE,
+            metric: diffimg::Metric::DeltaE(diffimg::DeltaEFormula::Ciede2000),
+            max_pixels: Some(0),
+            delta_e_tolerance: Some(1000.0),
+            ..Default::default()
+        };
+        assert_eq!(diffimg::run(config).unwrap(), diffimg::Verdict::Pass);
+
+        // Ignored pixels are left out of the total, as for the other metrics
+        let config = diffimg::Config {
+            image1: BLACK,
+            image2: WHITE,
+            metric: diffimg::Metric::DeltaE(diffimg::DeltaEFormula::Cie76),
+            ignore: vec!["0,0,10,3".parse().unwrap()],
+            ..Default::default()
+        };
+        let report = diffimg::compare(&config).unwrap();
+        let count = report.measurements[0].pixels.unwrap();
+        assert_eq!((count.differing, count.total), (70, 70));
+        assert_eq!(count.percentage(), 100.0);
+    }
+
+    #[test]
+    fn test_count_diff_pixels_tolerance() {
+        let image1 = image::open(MARIO_CS).unwrap();
+        let image2 = image::open(MARIO_NODE).unwrap();
+        let exact = diffimg::count_diff_pixels(&image1, &image2, 0);
+        let tolerant = diffimg::count_diff_pixels(&image1, &image2, 3);
+        assert!(exact.differing > 0);
+        assert!(tolerant.differing <= exact.differing);
+        assert_eq!(exact.total, 381 * 480);
+    }
+
+    #[test]
+    fn test_diff_image_grayscale() {
+        let image1 = DynamicImage::ImageLuma8(image::open(MARIO_CS).unwrap().to_luma8());
+        let image2 = DynamicImage::ImageLuma8(image::open(MARIO_NODE).unwrap().to_luma8());
+        let diff = diffimg::diff_image(&image1, &image2);
+        assert_eq!(diff.color(), image::ColorType::L8);
+        let expected: Vec<u8> = image1
+            .as_bytes()
+            .iter()
+            .zip(image2.as_bytes().iter())
+            .map(|(&p1, &p2)| p1.abs_diff(p2))
+            .collect();
+        assert_eq!(diff.as_bytes(), &expected[..]);
+    }
+
+    #[test]
+    fn test_create_diff_image_gray_alpha() {
+        let gray_alpha_diff_test = "tests/gray-alpha-diff.png";
+        let image1 = DynamicImage::ImageLumaA8(image::open(MARIO_CS).unwrap().to_luma_alpha8());
+        let image2 = DynamicImage::ImageLumaA8(image::open(MARIO_NODE).unwrap().to_luma_alpha8());
+        diffimg::create_diff_image(&image1, &image2, gray_alpha_diff_test).unwrap();
+        let written = image::open(gray_alpha_diff_test).unwrap();
+        fs::remove_file(gray_alpha_diff_test).unwrap();
+        assert_eq!(written.color(), image::ColorType::La8);
+        assert_eq!(
+            written.as_bytes(),
+            diffimg::diff_image(&image1, &image2).as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_diff_color_convert() {
+        let image1 = image::open(MARIO_CS).unwrap();
+        let image2 = image::open(MARIO_NODE).unwrap();
+        let diff = diffimg::diff_image(&image1, &image2);
+        let gray = diffimg::DiffColor::Gray.convert(&diff);
+        assert_eq!(gray.color(), image::ColorType::L8);
+        assert_eq!(gray.dimensions(), diff.dimensions());
+    }
+
+    #[test]
+    fn test_16bit_metrics() {
+        let image1 = image::open(MARIO_CS).unwrap();
+        let image2 = image::open(MARIO_NODE).unwrap();
+        let deep1 = DynamicImage::ImageRgba16(image1.to_rgba16());
+        let deep2 = DynamicImage::ImageRgba16(image2.to_rgba16());
+        assert_eq!(diffimg::max_channel_value(&deep1, &deep2), 65535.0);
+
+        // Widening 8-bit values to 16 bits scales them exactly, so normalised
+        // metrics are unchanged
+        let ratio = diffimg::calculate_diff_ratio(&image1, &image2);
+        let deep_ratio = diffimg::calculate_diff_ratio(&deep1, &deep2);
+        assert!((ratio - deep_ratio).abs() < 1e-12);
+        let psnr = diffimg::calculate_error_metrics(&image1, &image2).psnr;
+        let deep_psnr = diffimg::calculate_error_metrics(&deep1, &deep2).psnr;
+        assert!((psnr - deep_psnr).abs() < 1e-9);
+        assert_eq!(
+            diffimg::count_diff_pixels(&image1, &image2, 3),
+            diffimg::count_diff_pixels(&deep1, &deep2, 3)
+        );
+
+        let diff = diffimg::diff_image(&deep1, &deep2);
+        assert_eq!(diff.color(), image::ColorType::Rgba16);
+        let scaled = diffimg::Quantization::Scale.apply(&diff);
+        assert_eq!(
+            scaled.as_bytes(),
+            diffimg::diff_image(&image1, &image2).as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_float_diff_ratio() {
+        let image1 = DynamicImage::ImageRgb32F(
+            image::ImageBuffer::from_raw(1, 1, vec![0.0f32, 0.5, 4.0]).unwrap(),
+        );
+        let image2 = DynamicImage::ImageRgb32F(
+            image::ImageBuffer::from_raw(1, 1, vec![0.0f32, 0.5, 0.0]).unwrap(),
+        );
+        // HDR values above 1 raise the normalising maximum
+        assert_eq!(diffimg::max_channel_value(&image1, &image2), 4.0);
+        assert!((diffimg::calculate_diff_ratio(&image1, &image2) - 1.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_run_16bit_png() {
+        let deep_test = "tests/mario-16bit.png";
+        let image = image::open(MARIO_NODE).unwrap();
+        DynamicImage::ImageRgba16(image.to_rgba16())
+            .save(deep_test)
+            .unwrap();
+        let config = diffimg::Config {
+            image1: deep_test,
+            image2: deep_test,
+            threshold: Some(0.0),
+            ..Default::default()
+        };
+        let result = diffimg::run(config);
+        fs::remove_file(deep_test).unwrap();
+        assert_eq!(result.unwrap(), diffimg::Verdict::Pass);
+    }
+
+    #[test]
+    fn test_normalize_color() {
+        let black = image::open(BLACK).unwrap();
+        assert_eq!(black.color(), image::ColorType::Rgb8);
+        let normalized = diffimg::normalize_color(&black, diffimg::DEFAULT_NORMALIZED_COLOR);
+        assert_eq!(normalized.color(), image::ColorType::Rgba8);
+        // A missing alpha channel becomes fully opaque
+        assert!(normalized.as_bytes().chunks(4).all(|p| p == [0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_run_normalize_color() {
+        let rgba_test = "tests/black-rgba.png";
+        let black = image::open(BLACK).unwrap();
+        DynamicImage::ImageRgba8(black.to_rgba8())
+            .save(rgba_test)
+            .unwrap();
+
+        let config = diffimg::Config {
+            image1: BLACK,
+            image2: rgba_test,
+            ..Default::default()
+        };
+        let mismatch = diffimg::run(config);
+        let config = diffimg::Config {
+            image1: BLACK,
+            image2: rgba_test,
+            normalize_color: Some(diffimg::DEFAULT_NORMALIZED_COLOR),
+            threshold: Some(0.0),
+            ..Default::default()
+        };
+        let normalized = diffimg::run(config);
+        fs::remove_file(rgba_test).unwrap();
+
+        match mismatch {
+            Err(diffimg::DiffError::ColorMismatch { .. }) => {}
+            other => panic!("expected ColorMismatch, got {:?}", other),
+        }
+        assert_eq!(normalized.unwrap(), diffimg::Verdict::Pass);
+    }
+
+    #[test]
+    fn test_size_mismatch() {
+        let black = image::open(BLACK).unwrap();
+        let mario = image::open(MARIO_NODE).unwrap();
+
+        let (cropped1, cropped2) = diffimg::SizeMismatch::Crop
+            .apply(&black, &mario)
+            .unwrap()
+            .unwrap();
+        assert_eq!(cropped1.dimensions(), (10, 10));
+        assert_eq!(cropped2.dimensions(), (10, 10));
+
+        let white = image::Rgba([255, 255, 255, 255]);
+        let (padded1, padded2) = diffimg::SizeMismatch::Pad(white)
+            .apply(&black, &mario)
+            .unwrap()
+            .unwrap();
+        assert_eq!(padded1.dimensions(), (381, 480));
+        assert_eq!(padded1.color(), black.color());
+        assert_eq!(padded1.get_pixel(9, 9), image::Rgba([0, 0, 0, 255]));
+        assert_eq!(padded1.get_pixel(10, 10), white);
+        assert_eq!(padded2.as_bytes(), mario.as_bytes());
+        let padding = diffimg::SizeMismatch::Pad(white)
+            .padding(black.dimensions(), mario.dimensions())
+            .unwrap();
+        assert_eq!(padding.ignored_count(), 381 * 480 - 100);
+        assert!(diffimg::SizeMismatch::Crop
+            .padding(black.dimensions(), mario.dimensions())
+            .is_none());
+
+        let (resized1, resized2) = diffimg::SizeMismatch::Resize(FilterType::Nearest)
+            .apply(&mario, &black)
+            .unwrap()
+            .unwrap();
+        assert_eq!(resized1.dimensions(), (381, 480));
+        assert_eq!(resized2.dimensions(), (381, 480));
+
+        assert!(diffimg::SizeMismatch::Error
+            .apply(&black, &black)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_run_size_mismatch_pad() {
+        // Padding counts as differing even where it has the other image's color
+        let black_large = "tests/black-large.png";
+        let black = image::open(BLACK).unwrap();
+        DynamicImage::ImageRgb8(image::RgbImage::new(20, 15))
+            .save(black_large)
+            .unwrap();
+
+        let config = diffimg::Config {
+            image1: BLACK,
+            image2: black_large,
+            size_mismatch: diffimg::SizeMismatch::Pad(image::Rgba([0, 0, 0, 255])),
+            max_pixels: Some(0),
+            ..Default::default()
+        };
+        let same = diffimg::compare(&config);
+        let config = diffimg::Config {
+            image1: BLACK,
+            image2: black_large,
+            size_mismatch: diffimg::SizeMismatch::Pad(image::Rgba([255, 255, 255, 255])),
+            threshold: Some(0.0),
+            ..Default::default()
+        };
+        let padded_white = diffimg::run(config);
+        fs::remove_file(black_large).unwrap();
+
+        assert_eq!(black.dimensions(), (10, 10));
+        let same = same.unwrap();
+        assert_eq!(same.verdict, Some(diffimg::Verdict::Fail));
+        // 200 of the 20x15 pixels are padding, and differ fully
+        assert_eq!(same.measurements[0].value, 200.0 / 300.0);
+        let count = same.measurements[0].pixels.unwrap();
+        assert_eq!((count.differing, count.total), (200, 300));
+        assert_eq!(padded_white.unwrap(), diffimg::Verdict::Fail);
+    }
+
+    #[test]
+    fn test_masked_metrics() {
+        let black = image::open(BLACK).unwrap();
+        let mut clock = black.to_rgb8();
+        for (x, y, p) in clock.enumerate_pixels_mut() {
+            if (2..5).contains(&x) && y < 3 {
+                *p = image::Rgb([255, 255, 255]);
+            }
+        }
+        let clock = DynamicImage::ImageRgb8(clock);
+
+        let mut mask = diffimg::Mask::new(10, 10);
+        mask.ignore("2,0,3,3".parse().unwrap());
+        assert_eq!(
+            diffimg::calculate_masked_diff_ratio(&black, &clock, &mask),
+            0.0
+        );
+        let count = diffimg::count_masked_diff_pixels(&black, &clock, 0, &mask);
+        assert_eq!(count.differing, 0);
+        assert_eq!(count.total, 91);
+
+        // Ignoring only part of the change leaves the rest, relative to the included area
+        let mut mask = diffimg::Mask::new(10, 10);
+        mask.ignore("0,0,3,10".parse().unwrap());
+        assert_eq!(
+            diffimg::calculate_masked_diff_ratio(&black, &clock, &mask),
+            6.0 / 70.0
+        );
+        // The other metrics average over the included pixels too, as if only they
+        // had been compared
+        let rect = "3,0,7,10".parse().unwrap();
+        let (included1, included2) = (diffimg::crop(&black, rect), diffimg::crop(&clock, rect));
+        assert_eq!(
+            diffimg::calculate_masked_error_metrics(&black, &clock, &mask),
+            diffimg::calculate_error_metrics(&included1, &included2)
+        );
+        let formula = diffimg::DeltaEFormula::Cie76;
+        assert_eq!(
+            diffimg::delta_e_map(&black, &clock, formula).masked_mean(&mask),
+            diffimg::delta_e_map(&included1, &included2, formula).mean()
+        );
+        let ssim = diffimg::ssim_map(&black, &clock);
+        let included: Vec<f64> = (0..100)
+            .filter(|i| i % 10 >= 3)
+            .map(|i| ssim.values[i])
+            .collect();
+        assert_eq!(ssim.masked_mean(&mask), included.iter().sum::<f64>() / 70.0);
+        assert_eq!(ssim.masked_mean(&diffimg::Mask::new(10, 10)), ssim.mean());
+
+        let painted = mask.paint(
+            &diffimg::diff_image(&black, &clock),
+            diffimg::DEFAULT_MASK_COLOR,
+        );
+        assert_eq!(painted.get_pixel(0, 0), diffimg::DEFAULT_MASK_COLOR);
+        assert_eq!(painted.get_pixel(3, 0), image::Rgba([255, 255, 255, 255]));
+        assert_eq!(painted.get_pixel(3, 5), image::Rgba([0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_compare_report() {
+        let config = diffimg::Config {
+            image1: BLACK,
+            image2: WHITE,
+            max_pixels: Some(0),
+            boxes: true,
+            ..Default::default()
+        };
+        let report = diffimg::compare(&config).unwrap();
+        assert_eq!(report.image1.path, BLACK);
+        assert_eq!((report.width, report.height), (Some(10), Some(10)));
+        assert_eq!(report.verdict, Some(diffimg::Verdict::Fail));
+        assert_eq!(report.measurements.len(), 1);
+        let measurement = &report.measurements[0];
+        assert_eq!(measurement.value, 1.0);
+        assert_eq!(measurement.pixels.unwrap().differing, 100);
+        assert_eq!(report.boxes.as_ref().unwrap().len(), 1);
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["image2"]["path"], WHITE);
+        assert_eq!(json["metric"], "ratio");
+        assert_eq!(json["measurements"][0]["pixels"]["total"], 100);
+        assert_eq!(json["boxes"][0]["rect"]["width"], 10);
+        assert_eq!(json["verdict"], "fail");
+        assert_eq!(json["error"], serde_json::Value::Null);
+
+        // An infinite PSNR stays distinguishable from a missing value
+        let config = diffimg::Config {
+            image1: BLACK,
+            image2: BLACK,
+            metric: diffimg::Metric::Psnr,
+            ..Default::default()
+        };
+        let report = diffimg::compare(&config).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["measurements"][0]["value"], "inf");
+        assert_eq!(json["measurements"][0]["channels"][0], "inf");
+
+        // So do the statistics and boxes of floating-point images holding inf
+        let stats = diffimg::ChannelStats {
+            name: "R",
+            mean: f64::INFINITY,
+            max: f64::INFINITY,
+            std_dev: f64::NAN,
+            histogram: vec![],
+        };
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json["mean"], "inf");
+        assert_eq!(json["max"], "inf");
+        assert_eq!(json["std_dev"], "nan");
+        let change = diffimg::ChangeBox {
+            rect: "0,0,1,1".parse().unwrap(),
+            pixels: 1,
+            mean_intensity: f64::INFINITY,
+        };
+        let json = serde_json::to_value(change).unwrap();
+        assert_eq!(json["mean_intensity"], "inf");
+    }
+
+    #[test]
+    fn test_run_html_report() {
+        let report_path = "tests/report.html";
+        let config = diffimg::Config {
+            image1: BLACK,
+            image2: WHITE,
+            html_report: Some(report_path),
+            ..Default::default()
+        };
+        let verdict = diffimg::run(config).unwrap();
+        let html = fs::read_to_string(report_path).unwrap();
+        fs::remove_file(report_path).unwrap();
+
+        assert_eq!(verdict, diffimg::Verdict::Pass);
+        // Both inputs and the diff, plus the swipe and blink views
+        assert_eq!(html.matches("data:image/png;base64,").count(), 6);
+        assert!(html.contains("<td>whole image (10x10)</td><td>1</td>"));
+        assert!(html.contains("<tr><td>0,0,10,10</td><td>100</td>"));
+        assert!(html.contains("oninput=\"swipe(this.value)\""));
+        assert!(html.contains("width: 100%; height: 100%"));
+
+        // A legend makes the diff taller, and the boxes stay on the image above it
+        let config = diffimg::Config {
+            image1: BLACK,
+            image2: WHITE,
+            html_report: Some(report_path),
+            style: diffimg::DiffStyle::Heatmap,
+            legend: true,
+            ..Default::default()
+        };
+        diffimg::run(config).unwrap();
+        let html = fs::read_to_string(report_path).unwrap();
+        fs::remove_file(report_path).unwrap();
+        assert!(html.contains("top: 0%; width: 100%; height: 25.6"));
+
+        // The report shows the SSIM map for MS-SSIM and with regions, as -f does
+        let embedded = |metric, regions| {
+            let config = diffimg::Config {
+                image1: MARIO_NODE,
+                image2: MARIO_CS,
+                html_report: Some(report_path),
+                metric,
+                regions,
+                ..Default::default()
+            };
+            diffimg::run(config).unwrap();
+            let html = fs::read_to_string(report_path).unwrap();
+            fs::remove_file(report_path).unwrap();
+            html.split("data:image/png;base64,")
+                .skip(1)
+                .map(|uri| uri.split('"').next().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        let ssim = embedded(diffimg::Metric::Ssim, vec![]);
+        let region = diffimg::Region {
+            name: "top".to_string(),
+            rect: "0,0,10,10".parse().unwrap(),
+        };
+        assert_eq!(embedded(diffimg::Metric::MsSsim, vec![]), ssim);
+        assert_eq!(embedded(diffimg::Metric::Ssim, vec![region]), ssim);
+        assert_ne!(embedded(diffimg::Metric::Ratio, vec![]), ssim);
+    }
+
+    #[test]
+    fn test_compare_dirs() {
+        let (dir1, dir2, out) = ("tests/batch1", "tests/batch2", "tests/batch-diffs");
+        fs::create_dir_all(format!("{}/sub", dir1)).unwrap();
+        fs::create_dir_all(format!("{}/sub", dir2)).unwrap();
+        fs::copy(BLACK, format!("{}/same.png", dir1)).unwrap();
+        fs::copy(BLACK, format!("{}/same.png", dir2)).unwrap();
+        fs::copy(BLACK, format!("{}/sub/changed.png", dir1)).unwrap();
+        fs::copy(WHITE, format!("{}/sub/changed.png", dir2)).unwrap();
+        fs::copy(BLACK, format!("{}/removed.png", dir1)).unwrap();
+        fs::copy(WHITE, format!("{}/added.png", dir2)).unwrap();
+        // Not an image, so not compared
+        fs::write(format!("{}/notes.txt", dir1), "notes").unwrap();
+        // Links to directories are not followed, so this one does not loop
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("..", format!("{}/sub/loop", dir1)).unwrap();
+
+        let config = diffimg::Config {
+            image1: dir1,
+            image2: dir2,
+            filename: Some(out),
+            jobs: Some(1),
+            ..Default::default()
+        };
+        let report = diffimg::compare_dirs(&config).unwrap();
+        let config = diffimg::Config {
+            image1: dir1,
+            image2: dir2,
+            jobs: Some(4),
+            ..Default::default()
+        };
+        let parallel = diffimg::compare_dirs(&config).unwrap();
+        // The output directory can't be created below a file
+        let blocked = format!("{}/same.png/diffs", dir1);
+        let config = diffimg::Config {
+            filename: Some(&blocked),
+            ..config
+        };
+        let blocked = diffimg::compare_dirs(&config).unwrap_err();
+        let diff = image::open(format!("{}/sub/changed.png", out)).unwrap();
+        let unchanged_diff_written = fs::metadata(format!("{}/same.png", out)).is_ok();
+        fs::remove_dir_all(dir1).unwrap();
+        fs::remove_dir_all(dir2).unwrap();
+        fs::remove_dir_all(out).unwrap();
+
+        let statuses: Vec<(&str, diffimg::FileStatus)> = report
+            .entries
+            .iter()
+            .map(|e| (e.path.as_str(), e.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("added.png", diffimg::FileStatus::Added),
+                ("removed.png", diffimg::FileStatus::Removed),
+                ("same.png", diffimg::FileStatus::Unchanged),
+                ("sub/changed.png", diffimg::FileStatus::Changed),
+            ]
+        );
+        assert_eq!(report.verdict(), diffimg::Verdict::Fail);
+        // Ordered by path whatever order the threads finish in
+        let parallel_statuses: Vec<(&str, diffimg::FileStatus)> = parallel
+            .entries
+            .iter()
+            .map(|e| (e.path.as_str(), e.status))
+            .collect();
+        assert_eq!(parallel_statuses, statuses);
+        assert_eq!(diff.get_pixel(0, 0).0[0], 255);
+        assert!(!unchanged_diff_written);
+        let outputs = |i: usize| report.entries[i].report.as_ref().unwrap().outputs.clone();
+        assert_eq!(outputs(2).diff, None);
+        assert_eq!(outputs(3).diff, Some(format!("{}/sub/changed.png", out)));
+        assert_eq!(blocked.kind(), "create_dir");
+    }
+
+    #[test]
+    fn test_channel_stats() {
+        let black = image::open(BLACK).unwrap();
+        let white = image::open(WHITE).unwrap();
+        let stats = diffimg::calculate_channel_stats(&black, &white);
+        assert_eq!(stats.len(), usize::from(black.color().channel_count()));
+        let color = &stats[0];
+        assert_eq!((color.mean, color.max, color.std_dev), (255.0, 255.0, 0.0));
+        assert_eq!(color.histogram.len(), diffimg::HISTOGRAM_BINS);
+        assert_eq!(color.histogram[255], 100);
+
+        let same = diffimg::calculate_channel_stats(&black, &black);
+        assert!(same.iter().all(|c| c.max == 0.0 && c.histogram[0] == 100));
+    }
+
+    #[test]
+    fn test_run_alpha_modes() {
+        let path1 = "tests/alpha1.png";
+        let path2 = "tests/alpha2.png";
+        // Fully transparent, but with different hidden colors
+        image::RgbaImage::from_pixel(4, 4, image::Rgba([255, 0, 0, 0]))
+            .save(path1)
+            .unwrap();
+        image::RgbaImage::from_pixel(4, 4, image::Rgba([0, 0, 255, 0]))
+            .save(path2)
+            .unwrap();
+
+        let verdict = |alpha| {
+            let config = diffimg::Config {
+                image1: path1,
+                image2: path2,
+                alpha,
+                threshold: Some(0.0),
+                ..Default::default()
+            };
+            diffimg::run(config).unwrap()
+        };
+        let raw = verdict(diffimg::AlphaMode::Raw);
+        let composite = verdict(diffimg::AlphaMode::Composite(image::Rgba([0, 0, 0, 255])));
+        let transparent = verdict(diffimg::AlphaMode::TransparentEqual);
+        let ignore = verdict(diffimg::AlphaMode::Ignore);
+        fs::remove_file(path1).unwrap();
+        fs::remove_file(path2).unwrap();
+
+        assert_eq!(raw, diffimg::Verdict::Fail);
+        assert_eq!(composite, diffimg::Verdict::Pass);
+        assert_eq!(transparent, diffimg::Verdict::Pass);
+        assert_eq!(ignore, diffimg::Verdict::Fail);
+    }
+
+    #[test]
+    fn test_run_ignore_antialiasing() {
+        let path1 = "tests/edge1.png";
+        let path2 = "tests/edge2.png";
+        let diff_path = "tests/edge-diff.png";
+        // A black to white edge smoothed by a gray column of another shade in the
+        // second image, which also has one really changed pixel
+        let edge = |gray: u8| {
+            image::GrayImage::from_fn(5, 5, |x, _| match x {
+                0 | 1 => image::Luma([0]),
+                2 => image::Luma([gray]),
+                _ => image::Luma([255]),
+            })
+        };
+        edge(128).save(path1).unwrap();
+        let mut image2 = edge(100);
+        image2.put_pixel(0, 2, image::Luma([255]));
+        image2.save(path2).unwrap();
+
+        let config = diffimg::Config {
+            image1: path1,
+            image2: path2,
+            ignore_antialiasing: true,
+            filename: Some(diff_path),
+            max_pixels: Some(1),
+            ..Default::default()
+        };
+        let ignored = diffimg::run(config).unwrap();
+        let diff = image::open(diff_path).unwrap();
+        let config = diffimg::Config {
+            image1: path1,
+            image2: path2,
+            max_pixels: Some(1),
+            ..Default::default()
+        };
+        let counted = diffimg::run(config).unwrap();
+        fs::remove_file(path1).unwrap();
+        fs::remove_file(path2).unwrap();
+        fs::remove_file(diff_path).unwrap();
+
+        assert_eq!(ignored, diffimg::Verdict::Pass);
+        assert_eq!(counted, diffimg::Verdict::Fail);
+        assert_eq!(diff.get_pixel(2, 2), image::Rgba([255, 255, 0, 255]));
+        assert_eq!(diff.get_pixel(0, 2), image::Rgba([255, 255, 255, 255]));
+    }
+
+    #[test]
+    fn test_run_mask_image() {
+        let mask_path = "tests/mask.png";
+        let wrong_size_path = "tests/mask-wrong-size.png";
+        // Ignore the whole image except the first row
+        let mask =
+            image::GrayImage::from_fn(10, 10, |_, y| image::Luma([if y == 0 { 255 } else { 0 }]));
+        mask.save(mask_path).unwrap();
+        image::GrayImage::new(5, 5).save(wrong_size_path).unwrap();
+
+        let config = diffimg::Config {
+            image1: BLACK,
+            image2: WHITE,
+            mask: Some(mask_path),
+            ignore: vec!["0,0,10,1".parse().unwrap()],
+            threshold: Some(0.0),
+            ..Default::default()
+        };
+        let all_ignored = diffimg::run(config);
+        let config = diffimg::Config {
+            image1: BLACK,
+            image2: WHITE,
+            mask: Some(mask_path),
+            max_pixels: Some(9),
+            ..Default::default()
+        };
+        let first_row = diffimg::run(config);
+        let config = diffimg::Config {
+            image1: BLACK,
+            image2: WHITE,
+            mask: Some(wrong_size_path),
+            ..Default::default()
+        };
+        let wrong_size = diffimg::run(config);
+        fs::remove_file(mask_path).unwrap();
+        fs::remove_file(wrong_size_path).unwrap();
+
+        assert_eq!(all_ignored.unwrap(), diffimg::Verdict::Pass);
+        assert_eq!(first_row.unwrap(), diffimg::Verdict::Fail);
+        match wrong_size {
+            Err(diffimg::DiffError::MaskSize { mask, image }) => {
+                assert_eq!(mask, (5, 5));
+                assert_eq!(image, (10, 10));
+            }
+            other => panic!("expected MaskSize, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_region_diff_ratios() {
+        let mario_node = image::open(MARIO_NODE).unwrap();
+        let mario_cs = image::open(MARIO_CS).unwrap();
+        let whole = diffimg::Region::unnamed("0,0,381,480".parse().unwrap());
+        // Clipped to the image
+        let oversized = diffimg::Region::unnamed("0,0,1000,1000".parse().unwrap());
+        let corner = diffimg::Region::unnamed("0,0,10,10".parse().unwrap());
+        let ratios = diffimg::calculate_region_diff_ratios(
+            &mario_node,
+            &mario_cs,
+            &[whole, oversized, corner],
+        )
+        .unwrap();
+        assert_eq!(ratios[0], 0.007319618135968298);
+        assert_eq!(ratios[1], ratios[0]);
+        assert_eq!(ratios[2], 0.0);
+
+        let outside = diffimg::Region::unnamed("381,0,10,10".parse().unwrap());
+        match diffimg::calculate_region_diff_ratios(&mario_node, &mario_cs, &[outside]) {
+            Err(diffimg::DiffError::RegionOutside { name, image }) => {
+                assert_eq!(name, "381,0,10,10");
+                assert_eq!(image, (381, 480));
+            }
+            other => panic!("expected RegionOutside, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_run_region_file() {
+        let clock_path = "tests/clock.png";
+        let regions_path = "tests/regions.txt";
+        let mut clock = image::open(BLACK).unwrap().to_rgb8();
+        clock.put_pixel(9, 0, image::Rgb([255, 255, 255]));
+        clock.save(clock_path).unwrap();
+        fs::write(regions_path, "# name x,y,width,height\nbutton 0,5,10,5\n").unwrap();
+
+        let config = diffimg::Config {
+            image1: BLACK,
+            image2: clock_path,
+            region_file: Some(regions_path),
+            max_pixels: Some(0),
+            ..Default::default()
+        };
+        let unchanged = diffimg::run(config);
+        let config = diffimg::Config {
+            image1: BLACK,
+            image2: clock_path,
+            region_file: Some(regions_path),
+            regions: vec![diffimg::Region::unnamed("5,0,5,5".parse().unwrap())],
+            max_pixels: Some(0),
+            ..Default::default()
+        };
+        let changed = diffimg::run(config);
+        fs::remove_file(clock_path).unwrap();
+        fs::remove_file(regions_path).unwrap();
+
+        assert_eq!(unchanged.unwrap(), diffimg::Verdict::Pass);
+        assert_eq!(changed.unwrap(), diffimg::Verdict::Fail);
+    }
+
+    #[test]
+    fn test_find_changed_boxes() {
+        let black = image::open(BLACK).unwrap();
+        let mut changed = black.to_rgb8();
+        // Two touching pixels, one close by and one far away
+        changed.put_pixel(1, 1, image::Rgb([255, 255, 255]));
+        changed.put_pixel(2, 2, image::Rgb([255, 255, 255]));
+        changed.put_pixel(4, 1, image::Rgb([255, 0, 0]));
+        changed.put_pixel(9, 9, image::Rgb([255, 255, 255]));
+        let changed = DynamicImage::ImageRgb8(changed);
+
+        let boxes = diffimg::find_changed_boxes(&black, &changed, 0, 0, None);
+        let rects: Vec<String> = boxes.iter().map(|b| b.rect.to_string()).collect();
+        assert_eq!(rects, ["1,1,2,2", "4,1,1,1", "9,9,1,1"]);
+        assert_eq!(boxes[0].pixels, 2);
+        assert_eq!(boxes[0].mean_intensity, 1.0);
+        assert!((boxes[1].mean_intensity - 1.0 / 3.0).abs() < 1e-12);
+
+        let boxes = diffimg::find_changed_boxes(&black, &changed, 0, 1, None);
+        let rects: Vec<String> = boxes.iter().map(|b| b.rect.to_string()).collect();
+        assert_eq!(rects, ["1,1,4,2", "9,9,1,1"]);
+        assert_eq!(boxes[0].pixels, 3);
+
+        let drawn = diffimg::draw_boxes(&black, &boxes);
+        assert_eq!(*drawn.get_pixel(1, 1), diffimg::BOX_COLOR);
+        assert_eq!(*drawn.get_pixel(4, 2), diffimg::BOX_COLOR);
+        assert_eq!(*drawn.get_pixel(5, 5), image::Rgba([0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_run_overlay_style() {
+        let overlay_path = "tests/overlay.png";
+        let mut changed = image::open(BLACK).unwrap().to_rgb8();
+        changed.put_pixel(3, 4, image::Rgb([10, 0, 0]));
+        let changed_path = "tests/black-changed.png";
+        changed.save(changed_path).unwrap();
+
+        let config = diffimg::Config {
+            image1: BLACK,
+            image2: changed_path,
+            filename: Some(overlay_path),
+            style: diffimg::DiffStyle::Overlay,
+            highlight_color: Some(image::Rgba([0, 0, 255, 255])),
+            ..Default::default()
+        };
+        diffimg::run(config).unwrap();
+        let overlay = image::open(overlay_path).unwrap();
+        fs::remove_file(overlay_path).unwrap();
+        fs::remove_file(changed_path).unwrap();
+
+        assert_eq!(overlay.get_pixel(3, 4), image::Rgba([0, 0, 255, 255]));
+        assert_eq!(overlay.get_pixel(0, 0), image::Rgba([230, 230, 230, 255]));
+    }
+
+    #[test]
+    fn test_heatmap() {
+        let black = image::open(BLACK).unwrap();
+        let white = image::open(WHITE).unwrap();
+        let magnitude = diffimg::difference_magnitude(&black, &white);
+        assert!(magnitude.iter().all(|&m| m == 1.0));
+
+        let heatmap = diffimg::heatmap(&magnitude, 10, 10, diffimg::Colormap::Jet, (0.0, 2.0));
+        assert_eq!(*heatmap.get_pixel(0, 0), diffimg::Colormap::Jet.color(0.5));
+
+        let heatmap_path = "tests/heatmap.png";
+        let config = diffimg::Config {
+            image1: BLACK,
+            image2: WHITE,
+            filename: Some(heatmap_path),
+            style: diffimg::DiffStyle::Heatmap,
+            legend: true,
+            ..Default::default()
+        };
+        diffimg::run(config).unwrap();
+        let written = image::open(heatmap_path).unwrap();
+        fs::remove_file(heatmap_path).unwrap();
+        assert_eq!(written.width(), 10);
+        assert!(written.height() > 10);
+        assert_eq!(
+            written.get_pixel(5, 5),
+            diffimg::Colormap::Viridis.color(1.0)
+        );
+    }
+
+    #[test]
+    fn test_run_composite() {
+        let composite_path = "tests/composite.png";
+        let config = diffimg::Config {
+            image1: BLACK,
+            image2: WHITE,
+            filename: Some(composite_path),
+            composite: Some(diffimg::Composite {
+                layout: diffimg::Layout::Horizontal,
+                include_diff: true,
+                gutter: 2,
+            }),
+            ..Default::default()
+        };
+        diffimg::run(config).unwrap();
+        let horizontal = image::open(composite_path).unwrap();
+        let config = diffimg::Config {
+            image1: BLACK,
+            image2: WHITE,
+            filename: Some(composite_path),
+            composite: Some(diffimg::Composite {
+                layout: diffimg::Layout::Vertical,
+                include_diff: false,
+                gutter: 2,
+            }),
+            ..Default::default()
+        };
+        diffimg::run(config).unwrap();
+        let vertical = image::open(composite_path).unwrap();
+        fs::remove_file(composite_path).unwrap();
+
+        // Three 10x10 cells below 8 pixel high labels, with 2 pixel gutters
+        assert_eq!(horizontal.dimensions(), (38, 22));
+        assert_eq!(horizontal.get_pixel(2, 10), image::Rgba([0, 0, 0, 255]));
+        assert_eq!(
+            horizontal.get_pixel(14, 10),
+            image::Rgba([255, 255, 255, 255])
+        );
+        assert_eq!(vertical.dimensions(), (14, 42));
+    }
+
+    #[test]
+    fn test_run_blink() {
+        let gif_path = "tests/blink.gif";
+        let apng_path = "tests/blink.png";
+        let onion_path = "tests/onion.png";
+        let config = diffimg::Config {
+            image1: BLACK,
+            image2: WHITE,
+            blink: Some(gif_path),
+            blink_diff: true,
+            frame_delay: Some(100),
+            onion_skin: Some(onion_path),
+            onion_opacity: Some(0.25),
+            ..Default::default()
+        };
+        diffimg::run(config).unwrap();
+        let config = diffimg::Config {
+            image1: BLACK,
+            image2: WHITE,
+            blink: Some(apng_path),
+            ..Default::default()
+        };
+        diffimg::run(config).unwrap();
+
+        let gif = image::codecs::gif::GifDecoder::new(io::BufReader::new(
+            fs::File::open(gif_path).unwrap(),
+        ))
+        .unwrap();
+        let frames = gif.into_frames().collect_frames().unwrap();
+        let apng = image::codecs::png::PngDecoder::new(io::BufReader::new(
+            fs::File::open(apng_path).unwrap(),
+        ))
+        .unwrap();
+        let apng_frames = apng.apng().unwrap().into_frames().collect_frames().unwrap();
+        let onion = image::open(onion_path).unwrap();
+        fs::remove_file(gif_path).unwrap();
+        fs::remove_file(apng_path).unwrap();
+        fs::remove_file(onion_path).unwrap();
+
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].delay().numer_denom_ms(), (100, 1));
+        assert_eq!(
+            frames[0].buffer().get_pixel(0, 0),
+            &image::Rgba([0, 0, 0, 255])
+        );
+        assert_eq!(
+            frames[1].buffer().get_pixel(0, 0),
+            &image::Rgba([255, 255, 255, 255])
+        );
+        assert_eq!(apng_frames.len(), 2);
+        assert_eq!(onion.get_pixel(0, 0), image::Rgba([64, 64, 64, 255]));
+
+        // Formats that cannot hold an animation leave no file behind
+        let jpeg_path = "tests/blink.jpg";
+        let config = diffimg::Config {
+            image1: BLACK,
+            image2: WHITE,
+            blink: Some(jpeg_path),
+            ..Default::default()
+        };
+        assert!(matches!(
+            diffimg::run(config),
+            Err(diffimg::DiffError::Save { .. })
+        ));
+        assert!(fs::metadata(jpeg_path).is_err());
+    }
+
+    #[test]
+    fn test_run_onion_skin_ignore() {
+        let onion_path = "tests/onion_ignore.png";
+        let config = diffimg::Config {
+            image1: BLACK,
+            image2: WHITE,
+            ignore: vec!["0,0,10,10".parse().unwrap()],
+            onion_skin: Some(onion_path),
+            onion_opacity: Some(1.0),
+            ..Default::default()
+        };
+        let result = diffimg::run(config);
+        let onion = image::open(onion_path).unwrap();
+        fs::remove_file(onion_path).unwrap();
+
+        // The ignored pixels compare as equal, but the onion skin still shows the
+        // second image as it is
+        assert_eq!(result.unwrap(), diffimg::Verdict::Pass);
+        assert_eq!(onion.get_pixel(5, 5), image::Rgba([255, 255, 255, 255]));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_parallel_matches_serial() {
+        let node = image::open(MARIO_NODE).unwrap();
+        let cs = image::open(MARIO_CS).unwrap();
+        let float = |image: &DynamicImage| DynamicImage::ImageRgba32F(image.to_rgba32f());
+        let mut mask = diffimg::Mask::new(node.width(), node.height());
+        mask.ignore("10,10,50,40".parse().unwrap());
+
+        // Every result, printed with enough digits to tell any two floats apart
+        let results = || {
+            let mut out = String::new();
+            for (image1, image2) in [(node.clone(), cs.clone()), (float(&node), float(&cs))] {
+                let (i1, i2) = (&image1, &image2);
+                out += &format!(
+                    "{:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?}\n",
+                    diffimg::calculate_diff_ratio(i1, i2),
+                    diffimg::calculate_masked_diff_ratio(i1, i2, &mask),
+                    diffimg::count_diff_pixels(i1, i2, 3),
+                    diffimg::diff_image(i1, i2).as_bytes(),
+                    diffimg::ssim_map(i1, i2).values,
+                    diffimg::calculate_ms_ssim(i1, i2),
+                    diffimg::calculate_error_metrics(i1, i2),
+                    diffimg::delta_e_map(i1, i2, diffimg::DeltaEFormula::Ciede2000).values,
+                    diffimg::calculate_channel_stats(i1, i2),
+                    diffimg::find_changed_boxes(i1, i2, 0, 5, Some(&mask)),
+                );
+            }
+            out
+        };
+        // On one thread, the chunks are processed in the same plain loop as without
+        // the feature
+        let pool = |threads| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+        };
+        let serial = pool(1).install(results);
+        let parallel = pool(4).install(results);
+        assert!(serial == parallel);
+    }
 }